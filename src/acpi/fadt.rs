@@ -0,0 +1,62 @@
+//! Fixed ACPI Description Table: the power management hardware
+//!
+//! See https://wiki.osdev.org/FADT
+//!
+//! Only the fields of ACPI 1.0 are parsed, they are enough on a PC (I/O
+//! ports rather than the generic addresses of the later versions).
+
+use x86_64::PhysAddr;
+
+use super::{AcpiError, Table};
+
+/// `iapc_boot_arch` bit: there is a 8042 PS/2 controller
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// Differentiated System Description Table, AML code
+    pub dsdt: PhysAddr,
+    /// ISA IRQ of the system control interrupt
+    pub sci_interrupt: u16,
+    /// Port to write `acpi_enable` or `acpi_disable` to, 0 if ACPI is always enabled
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    /// Port of the power management timer, 0 if there is none
+    pub pm_timer_block: u32,
+    /// CMOS register of the century, 0 if there is none
+    pub century_register: u8,
+    /// IA-PC boot architecture flags, 0 before ACPI 2.0
+    pub iapc_boot_arch: u16,
+    pub flags: u32,
+}
+
+impl Fadt {
+    pub(super) fn parse(table: &Table) -> Result<Fadt, AcpiError> {
+        Ok(Fadt {
+            dsdt: PhysAddr::new(table.read_field::<u32>(40)? as u64),
+            sci_interrupt: table.read_field(46)?,
+            smi_command_port: table.read_field(48)?,
+            acpi_enable: table.read_field(52)?,
+            acpi_disable: table.read_field(53)?,
+            pm1a_event_block: table.read_field(56)?,
+            pm1b_event_block: table.read_field(60)?,
+            pm1a_control_block: table.read_field(64)?,
+            pm1b_control_block: table.read_field(68)?,
+            pm_timer_block: table.read_field(76)?,
+            century_register: table.read_field(108)?,
+            // Reserved in ACPI 1.0, hence 0
+            iapc_boot_arch: table.read_field(109)?,
+            flags: table.read_field(112)?,
+        })
+    }
+
+    /// Whether there is a PS/2 controller, assumed when the firmware doesn't say
+    pub fn has_8042(&self) -> bool {
+        self.iapc_boot_arch == 0 || self.iapc_boot_arch & BOOT_ARCH_8042 != 0
+    }
+}
@@ -0,0 +1,48 @@
+//! High Precision Event Timer description
+//!
+//! See https://wiki.osdev.org/HPET
+
+use x86_64::PhysAddr;
+
+use super::{AcpiError, Table};
+
+/// Address space id of the base address: memory rather than I/O ports
+const SYSTEM_MEMORY: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    /// Number of comparators, i.e. of timers
+    pub comparators: u8,
+    /// The main counter is 64 bits wide
+    pub counter_64bit: bool,
+    /// The first timers can replace the PIT and the RTC
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// Physical address of the registers
+    pub address: PhysAddr,
+    /// Sequence number of this HPET
+    pub number: u8,
+    /// Minimum period of the periodic mode, in main counter ticks
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    pub(super) fn parse(table: &Table) -> Result<Hpet, AcpiError> {
+        let block_id = table.read_field::<u32>(36)?;
+        if table.read_field::<u8>(40)? != SYSTEM_MEMORY {
+            return Err(AcpiError::InvalidTable(table.signature));
+        }
+
+        Ok(Hpet {
+            hardware_revision: block_id as u8,
+            comparators: ((block_id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: block_id & (1 << 13) != 0,
+            legacy_replacement: block_id & (1 << 15) != 0,
+            pci_vendor_id: (block_id >> 16) as u16,
+            address: PhysAddr::new(table.read_field(44)?),
+            number: table.read_field(52)?,
+            minimum_tick: table.read_field(53)?,
+        })
+    }
+}
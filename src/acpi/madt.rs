@@ -0,0 +1,137 @@
+//! Multiple APIC Description Table: the CPUs and the interrupt controllers
+//!
+//! See https://wiki.osdev.org/MADT
+
+use x86_64::PhysAddr;
+
+use super::{AcpiError, Table, HEADER_SIZE};
+
+/// Maximum number of entries of each kind kept, the next ones are ignored
+pub const MAX_PROCESSORS: usize = 32;
+pub const MAX_IO_APICS: usize = 4;
+pub const MAX_OVERRIDES: usize = 16;
+
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_OVERRIDE: u8 = 2;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// A CPU, through its local APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// Usable right away
+    pub enabled: bool,
+    /// Not enabled, but can be at runtime
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// First global system interrupt handled
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ which isn't identity mapped to a global system interrupt, or
+/// whose polarity or trigger mode isn't the ISA one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Madt {
+    /// Physical address of the local APICs
+    pub local_apic: PhysAddr,
+    /// The 8259 PICs are there too, to be masked when using the APICs
+    pub pic_compatible: bool,
+    processors: [Option<Processor>; MAX_PROCESSORS],
+    io_apics: [Option<IoApic>; MAX_IO_APICS],
+    overrides: [Option<InterruptOverride>; MAX_OVERRIDES],
+}
+
+/// Puts `value` in the first free slot, if any
+fn push<T>(slots: &mut [Option<T>], value: T) {
+    if let Some(slot) = slots.iter_mut().find(|s| s.is_none()) {
+        *slot = Some(value);
+    }
+}
+
+impl Madt {
+    pub(super) fn parse(table: &Table) -> Result<Madt, AcpiError> {
+        let mut madt = Madt {
+            local_apic: PhysAddr::new(table.read_field::<u32>(HEADER_SIZE)? as u64),
+            pic_compatible: table.read_field::<u32>(HEADER_SIZE + 4)? & 1 != 0,
+            processors: [None; MAX_PROCESSORS],
+            io_apics: [None; MAX_IO_APICS],
+            overrides: [None; MAX_OVERRIDES],
+        };
+
+        // Entries of variable length follow, each starts with its type and length
+        let mut offset = HEADER_SIZE + 8;
+        while offset + 2 <= table.length as usize {
+            let kind = table.read_field::<u8>(offset)?;
+            let length = table.read_field::<u8>(offset + 1)? as usize;
+            if length < 2 {
+                return Err(AcpiError::InvalidTable(table.signature));
+            }
+
+            match kind {
+                LOCAL_APIC => {
+                    let flags = table.read_field::<u32>(offset + 4)?;
+                    push(&mut madt.processors, Processor {
+                        processor_id: table.read_field(offset + 2)?,
+                        apic_id: table.read_field(offset + 3)?,
+                        enabled: flags & 1 != 0,
+                        online_capable: flags & 2 != 0,
+                    });
+                }
+                IO_APIC => push(&mut madt.io_apics, IoApic {
+                    id: table.read_field(offset + 2)?,
+                    address: PhysAddr::new(table.read_field::<u32>(offset + 4)? as u64),
+                    gsi_base: table.read_field(offset + 8)?,
+                }),
+                INTERRUPT_OVERRIDE => {
+                    // 0b11 for active low and for level triggered, 0b00 is what the bus uses
+                    let flags = table.read_field::<u16>(offset + 8)?;
+                    push(&mut madt.overrides, InterruptOverride {
+                        irq: table.read_field(offset + 3)?,
+                        gsi: table.read_field(offset + 4)?,
+                        active_low: flags & 0b11 == 0b11,
+                        level_triggered: (flags >> 2) & 0b11 == 0b11,
+                    });
+                }
+                LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    madt.local_apic = PhysAddr::new(table.read_field(offset + 4)?);
+                }
+                _ => {}
+            }
+            offset += length;
+        }
+        Ok(madt)
+    }
+
+    pub fn processors(&self) -> impl Iterator<Item = &Processor> {
+        self.processors.iter().flatten()
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = &IoApic> {
+        self.io_apics.iter().flatten()
+    }
+
+    pub fn overrides(&self) -> impl Iterator<Item = &InterruptOverride> {
+        self.overrides.iter().flatten()
+    }
+
+    /// The I/O APIC handling the global system interrupt `gsi`, i.e. the one
+    /// with the highest base not above it
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics().filter(|a| a.gsi_base <= gsi).max_by_key(|a| a.gsi_base)
+    }
+}
@@ -0,0 +1,259 @@
+//! ACPI tables
+//!
+//! See https://wiki.osdev.org/RSDP and https://wiki.osdev.org/RSDT
+//!
+//! `init` finds the RSDP in the BIOS memory, follows it to the RSDT (or to
+//! the XSDT since ACPI 2.0) and parses the tables the kernel cares about:
+//! the MADT (CPUs and interrupt controllers), the FADT (power management)
+//! and the HPET. The tables are read through the physical memory mapping
+//! and nothing is allocated, the result is kept for good, see `get`.
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::Madt;
+
+use core::fmt;
+use core::mem::size_of;
+
+use conquer_once::spin::OnceCell;
+use x86_64::PhysAddr;
+
+use crate::memory::physical_memory_offset;
+
+/// Maximum number of tables listed by the RSDT, the next ones are ignored
+pub const MAX_TABLES: usize = 32;
+
+/// Size of `SdtHeader`, where the content of every table starts
+pub const HEADER_SIZE: usize = 36;
+
+/// Signature of a table, e.g. `APIC` for the MADT
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl Signature {
+    pub const RSDP: Signature = Signature(*b"RSDP");
+    pub const MADT: Signature = Signature(*b"APIC");
+    pub const FADT: Signature = Signature(*b"FACP");
+    pub const HPET: Signature = Signature(*b"HPET");
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &c in &self.0 {
+            write!(f, "{}", if c.is_ascii_graphic() { c as char } else { '?' })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP in the BIOS memory, the firmware may not support ACPI
+    RsdpNotFound,
+    /// The bytes of the table (or of the RSDP) don't sum to 0
+    BadChecksum(Signature),
+    /// The table is shorter than what it holds
+    InvalidTable(Signature),
+}
+
+
+/// Reads a `T` at the physical address `addr`
+///
+/// This function is unsafe because the memory must be mapped and hold a `T`.
+unsafe fn read_phys<T: Copy>(addr: PhysAddr) -> T {
+    core::ptr::read_unaligned((physical_memory_offset() + addr.as_u64()).as_ptr())
+}
+
+/// Sum of the `len` bytes at `addr`, valid tables sum to 0
+unsafe fn checksum(addr: PhysAddr, len: usize) -> u8 {
+    let bytes = core::slice::from_raw_parts((physical_memory_offset() + addr.as_u64()).as_ptr::<u8>(), len);
+    bytes.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+
+/// Header common to the tables
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+#[allow(dead_code)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// A table whose checksum was checked
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub address: PhysAddr,
+    pub signature: Signature,
+    /// In bytes, header included
+    pub length: u32,
+    pub revision: u8,
+}
+
+impl Table {
+    /// Checks the table at `address`
+    ///
+    /// This function is unsafe because `address` must be the address of a table.
+    unsafe fn load(address: PhysAddr) -> Result<Table, AcpiError> {
+        let header: SdtHeader = read_phys(address);
+        let signature = Signature(header.signature);
+        let length = header.length;
+        if (length as usize) < HEADER_SIZE {
+            return Err(AcpiError::InvalidTable(signature));
+        }
+        if checksum(address, length as usize) != 0 {
+            return Err(AcpiError::BadChecksum(signature));
+        }
+        Ok(Table { address, signature, length, revision: header.revision })
+    }
+
+    /// Reads the `T` at `offset` bytes from the start of the table, `None`
+    /// past its end
+    pub fn read<T: Copy>(&self, offset: usize) -> Option<T> {
+        if offset + size_of::<T>() > self.length as usize {
+            return None;
+        }
+        Some(unsafe { read_phys(self.address + offset as u64) })
+    }
+
+    /// Like `read`, but a table too short for the field is invalid
+    fn read_field<T: Copy>(&self, offset: usize) -> Result<T, AcpiError> {
+        self.read(offset).ok_or(AcpiError::InvalidTable(self.signature))
+    }
+}
+
+
+/// Root System Description Pointer, the first 20 bytes are the ACPI 1.0 one
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+#[allow(dead_code)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const RSDP_V1_SIZE: usize = 20;
+
+/// Finds the RSDP in the first KiB of the EBDA or in the BIOS ROM, on a 16
+/// bytes boundary
+fn find_rsdp() -> Option<PhysAddr> {
+    // The real mode segment of the EBDA is at 0x40e
+    let ebda = (unsafe { read_phys::<u16>(PhysAddr::new(0x40e)) } as u64) << 4;
+    let areas = [(ebda, ebda + 1024), (0xe0000, 0x100000)];
+
+    areas.iter()
+        .filter(|&&(start, _)| start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| unsafe {
+            read_phys::<[u8; 8]>(addr) == *b"RSD PTR " && checksum(addr, RSDP_V1_SIZE) == 0
+        })
+}
+
+
+/// What was found in the ACPI tables
+#[derive(Debug)]
+pub struct Acpi {
+    /// 0 for ACPI 1.0, 2 and above since ACPI 2.0
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    tables: [Option<Table>; MAX_TABLES],
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+}
+
+impl Acpi {
+    /// The tables listed by the RSDT, except those whose checksum is wrong
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.tables.iter().flatten()
+    }
+
+    pub fn find_table(&self, signature: Signature) -> Option<Table> {
+        self.tables().find(|t| t.signature == signature).copied()
+    }
+}
+
+static ACPI: OnceCell<Acpi> = OnceCell::uninit();
+
+/// Finds and parses the ACPI tables, must be called after `memory::init`
+///
+/// Once it succeeded, it returns what was found the first time.
+pub fn init() -> Result<&'static Acpi, AcpiError> {
+    if let Some(acpi) = get() {
+        return Ok(acpi);
+    }
+    let acpi = unsafe { parse()? };
+    let _ = ACPI.try_init_once(|| acpi);
+    Ok(get().expect("ACPI tables not initialized"))
+}
+
+/// The ACPI tables, `None` until `init` succeeded
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.try_get().ok()
+}
+
+unsafe fn parse() -> Result<Acpi, AcpiError> {
+    let rsdp_address = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+    let rsdp: Rsdp = read_phys(rsdp_address);
+
+    // The XSDT lists 64 bits addresses, the RSDT 32 bits ones
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        if checksum(rsdp_address, rsdp.length as usize) != 0 {
+            return Err(AcpiError::BadChecksum(Signature::RSDP));
+        }
+        (Table::load(PhysAddr::new(rsdp.xsdt_address))?, 8)
+    } else {
+        (Table::load(PhysAddr::new(rsdp.rsdt_address as u64))?, 4)
+    };
+
+    let mut acpi = Acpi {
+        revision: rsdp.revision,
+        oem_id: rsdp.oem_id,
+        tables: [None; MAX_TABLES],
+        madt: None,
+        fadt: None,
+        hpet: None,
+    };
+
+    let count = (root.length as usize - HEADER_SIZE) / entry_size;
+    for (i, slot) in (0..count).zip(acpi.tables.iter_mut()) {
+        let offset = HEADER_SIZE + i * entry_size;
+        let address = if entry_size == 8 {
+            root.read_field::<u64>(offset)?
+        } else {
+            root.read_field::<u32>(offset)? as u64
+        };
+        // A broken table is as good as a missing one
+        *slot = Table::load(PhysAddr::new(address)).ok();
+    }
+
+    acpi.madt = acpi.find_table(Signature::MADT).map(|t| Madt::parse(&t)).transpose()?;
+    acpi.fadt = acpi.find_table(Signature::FADT).map(|t| Fadt::parse(&t)).transpose()?;
+    acpi.hpet = acpi.find_table(Signature::HPET).map(|t| Hpet::parse(&t)).transpose()?;
+    Ok(acpi)
+}
@@ -0,0 +1,217 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, Page, PageSize, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use crate::memory::{self, KernelMemory, MapError};
+
+pub mod slab;
+pub mod stats;
+#[cfg(feature = "heap_sanitizer")]
+pub mod sanitizer;
+
+#[cfg(feature = "heap_sanitizer")]
+use sanitizer::Sanitized;
+#[cfg(feature = "slab_allocator")]
+use slab::SlabAllocator;
+use stats::{HeapStats, Tracked};
+
+#[cfg(feature = "heap_debug")]
+pub use stats::{leak_mark, leaks_since, report_leaks_since};
+
+pub const HEAP_START: usize = 0x_4444_beef_0000;
+/// Size of the heap when it is created
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Default ceiling the heap can grow up to, see `set_max_size`
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+/// The heap never grows by less than this, to avoid mapping pages one by one
+const GROW_STEP: usize = 64 * 1024;
+
+static MAX_SIZE: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+
+/// The heap at the bottom of the allocator stack, mapping pages as needed
+static HEAP: GrowableHeap = GrowableHeap::empty();
+
+/// What the global allocator is built on: the slab allocator, or the heap itself
+#[cfg(feature = "slab_allocator")]
+type Backend = SlabAllocator<GrowableHeap>;
+#[cfg(feature = "slab_allocator")]
+static BACKEND: Backend = SlabAllocator::new(&HEAP);
+
+#[cfg(not(feature = "slab_allocator"))]
+type Backend = GrowableHeap;
+#[cfg(not(feature = "slab_allocator"))]
+use self::HEAP as BACKEND;
+
+#[cfg(feature = "heap_sanitizer")]
+static SANITIZER: Sanitized<Backend> = Sanitized::new(&BACKEND);
+
+#[cfg(not(feature = "heap_sanitizer"))]
+#[global_allocator]
+static ALLOCATOR: Tracked<Backend> = Tracked::new(&BACKEND);
+
+#[cfg(feature = "heap_sanitizer")]
+#[global_allocator]
+static ALLOCATOR: Tracked<Sanitized<Backend>> = Tracked::new(&SANITIZER);
+
+
+
+/// Maps the first `HEAP_SIZE` bytes of the heap and initializes the allocator.
+///
+/// `memory::install` must have been called before.
+pub fn init_heap() -> Result<(), MapError> {
+    let mut mapped = 0;
+    memory::with_kernel_memory(|mem| map_heap_pages(mem, HEAP_START, HEAP_SIZE, &mut mapped))?;
+
+    unsafe {
+        HEAP.heap.lock().init(HEAP_START, mapped);
+    }
+
+    Ok(())
+}
+
+/// Sets how big the heap is allowed to grow, in bytes.
+///
+/// The heap never shrinks, so lowering the limit below the current size
+/// only prevents further growth.
+pub fn set_max_size(size: usize) {
+    MAX_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Current size of the heap (mapped memory), in bytes
+pub fn heap_size() -> usize {
+    HEAP.heap.lock().size()
+}
+
+
+/// Returns a snapshot of the heap usage
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+/// Prints the heap usage on the serial console
+pub fn print_stats() {
+    crate::serial_print!("{}", stats());
+}
+
+/// Checks the freed blocks kept by the sanitizer and really frees them
+#[cfg(feature = "heap_sanitizer")]
+pub fn flush_quarantine() {
+    SANITIZER.flush_quarantine();
+}
+
+
+/// Maps the pages of `[start, start+size)` to fresh frames.
+///
+/// `mapped` is increased by the number of bytes mapped, even if an error
+/// occurs halfway through.
+fn map_heap_pages(mem: &mut KernelMemory, start: usize, size: usize, mapped: &mut usize)
+    -> Result<(), MapError>
+{
+    let page_range = {
+        let heap_start = VirtAddr::new(start as u64);
+        let heap_end = heap_start + size - 1u64;
+        let heap_start_page = Page::containing_address(heap_start);
+        let heap_end_page = Page::containing_address(heap_end);
+        Page::range_inclusive(heap_start_page, heap_end_page)
+    };
+
+    let KernelMemory { mapper, frame_allocator } = mem;
+
+    for page in page_range {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        unsafe {
+            // Don't forget The flush refreshes the (T)ranslation (L)ookaside (B)uffer
+            mapper.map(page, frame, flags, frame_allocator)?.flush()
+        };
+        *mapped += page.size() as usize;
+    }
+
+    Ok(())
+}
+
+
+/// A linked list heap which maps more pages when it runs out of memory
+///
+/// It grows up to the limit set by `set_max_size`, mapping pages through
+/// `memory::with_kernel_memory`.
+pub struct GrowableHeap {
+    heap: Mutex<Heap>,
+}
+
+impl GrowableHeap {
+    pub const fn empty() -> Self {
+        Self { heap: Mutex::new(Heap::empty()) }
+    }
+
+    /// Grows the heap enough so that `layout` fits at its end
+    fn grow(heap: &mut Heap, layout: Layout) -> Result<(), ()> {
+        // Worst case the allocation must be aligned past the current top
+        let needed = align_up(layout.size() + layout.align(), Size4KiB::SIZE as usize);
+        let available = MAX_SIZE.load(Ordering::Relaxed).saturating_sub(heap.size());
+        let by = GROW_STEP.max(needed).min(available);
+        if by < needed {
+            return Err(());
+        }
+
+        // Whatever got mapped before an error is still usable
+        let mut mapped = 0;
+        let _ = memory::with_kernel_memory(|mem| map_heap_pages(mem, heap.top(), by, &mut mapped));
+        unsafe { heap.extend(mapped) };
+
+        if mapped >= needed { Ok(()) } else { Err(()) }
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        match Self::grow(&mut heap, layout) {
+            Ok(()) => heap.allocate_first_fit(layout).map_or(null_mut(), |ptr| ptr.as_ptr()),
+            Err(()) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
+
+
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+
+
+pub struct Dummy;
+
+unsafe impl GlobalAlloc for Dummy {
+    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+        null_mut()
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        panic!("dealloc should be never called")
+    }
+}
@@ -0,0 +1,169 @@
+//! Heap sanitizer, enabled by the `heap_sanitizer` feature
+//!
+//! `Sanitized` wraps the allocator doing the work and surrounds every
+//! allocation with red zones filled with `REDZONE_BYTE`. Freed blocks are
+//! filled with `FREED_BYTE` and kept in a quarantine for a while instead of
+//! being reused right away.
+//!
+//! Red zones are checked when a block is freed and again when it leaves the
+//! quarantine, where the poison is checked too. Any damage means something
+//! wrote out of bounds or after free, and the allocator panics with the
+//! address and the layout of the block.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use spin::Mutex;
+
+/// Minimum size of the red zones around an allocation, the one in front is
+/// as big as the alignment if that's more
+pub const REDZONE_SIZE: usize = 16;
+/// Pattern of the red zones
+pub const REDZONE_BYTE: u8 = 0xfb;
+/// Pattern of freed memory
+pub const FREED_BYTE: u8 = 0xfd;
+
+/// Number of freed blocks kept at most
+pub const QUARANTINE_LEN: usize = 256;
+/// Bytes kept in quarantine at most, red zones included
+pub const QUARANTINE_BYTES: usize = 256 * 1024;
+
+
+/// A freed block waiting in quarantine, as seen by the user
+#[derive(Clone, Copy)]
+struct Quarantined {
+    ptr: usize,
+    layout: Layout,
+}
+
+/// Ring of the freed blocks, the oldest is given back first
+struct Quarantine {
+    blocks: [Option<Quarantined>; QUARANTINE_LEN],
+    /// Index of the oldest block
+    head: usize,
+    len: usize,
+    bytes: usize,
+}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Quarantine { blocks: [None; QUARANTINE_LEN], head: 0, len: 0, bytes: 0 }
+    }
+
+    fn contains(&self, ptr: usize) -> bool {
+        self.blocks.iter().flatten().any(|block| block.ptr == ptr)
+    }
+
+    fn push(&mut self, block: Quarantined) {
+        debug_assert!(self.len < QUARANTINE_LEN);
+        self.blocks[(self.head + self.len) % QUARANTINE_LEN] = Some(block);
+        self.len += 1;
+        self.bytes += padded(&block.layout).0.size();
+    }
+
+    fn pop(&mut self) -> Option<Quarantined> {
+        let block = self.blocks[self.head].take()?;
+        self.head = (self.head + 1) % QUARANTINE_LEN;
+        self.len -= 1;
+        self.bytes -= padded(&block.layout).0.size();
+        Some(block)
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == QUARANTINE_LEN || self.bytes > QUARANTINE_BYTES
+    }
+}
+
+
+/// Layout given to the inner allocator for `layout`, with the offset of the
+/// user's block in it
+fn padded(layout: &Layout) -> (Layout, usize) {
+    let front = REDZONE_SIZE.max(layout.align());
+    let size = front + layout.size() + REDZONE_SIZE;
+    (unsafe { Layout::from_size_align_unchecked(size, layout.align()) }, front)
+}
+
+/// Offset of the first byte of `len` bytes at `ptr` which isn't `pattern`
+unsafe fn find_not(ptr: *const u8, len: usize, pattern: u8) -> Option<usize> {
+    core::slice::from_raw_parts(ptr, len).iter().position(|&b| b != pattern)
+}
+
+/// Panics if the red zones around the block at `ptr` were overwritten
+unsafe fn check_redzones(ptr: *mut u8, layout: &Layout) {
+    let front = padded(layout).1;
+    if let Some(offset) = find_not(ptr.sub(front), front, REDZONE_BYTE) {
+        panic!("heap sanitizer: red zone before {:p} overwritten {} bytes before it, {:?}",
+            ptr, front - offset, layout);
+    }
+    if let Some(offset) = find_not(ptr.add(layout.size()), REDZONE_SIZE, REDZONE_BYTE) {
+        panic!("heap sanitizer: red zone after {:p} overwritten {} bytes after its end, {:?}",
+            ptr, offset, layout);
+    }
+}
+
+
+/// Allocator wrapper detecting out of bounds writes, double frees and
+/// writes after free
+pub struct Sanitized<A: 'static> {
+    inner: &'static A,
+    quarantine: Mutex<Quarantine>,
+}
+
+impl<A: GlobalAlloc> Sanitized<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        Self {
+            inner,
+            quarantine: Mutex::new(Quarantine::new()),
+        }
+    }
+
+    /// Checks the blocks in quarantine and gives them all back to the inner allocator
+    pub fn flush_quarantine(&self) {
+        let mut quarantine = self.quarantine.lock();
+        while let Some(block) = quarantine.pop() {
+            unsafe { self.release(block) };
+        }
+    }
+
+    /// Checks a block leaving the quarantine and frees it
+    unsafe fn release(&self, block: Quarantined) {
+        let ptr = block.ptr as *mut u8;
+        check_redzones(ptr, &block.layout);
+        if let Some(offset) = find_not(ptr, block.layout.size(), FREED_BYTE) {
+            panic!("heap sanitizer: {:p} written at offset {} after being freed, {:?}",
+                ptr, offset, block.layout);
+        }
+
+        let (padded, front) = padded(&block.layout);
+        self.inner.dealloc(ptr.sub(front), padded);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Sanitized<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (padded, front) = padded(&layout);
+        let base = self.inner.alloc(padded);
+        if base.is_null() {
+            return null_mut();
+        }
+
+        base.write_bytes(REDZONE_BYTE, front);
+        base.add(front + layout.size()).write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+        base.add(front)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut quarantine = self.quarantine.lock();
+        if quarantine.contains(ptr as usize) {
+            panic!("heap sanitizer: double free of {:p}, {:?}", ptr, layout);
+        }
+        check_redzones(ptr, &layout);
+
+        ptr.write_bytes(FREED_BYTE, layout.size());
+        while quarantine.is_full() {
+            let oldest = quarantine.pop().expect("full quarantine is empty");
+            self.release(oldest);
+        }
+        quarantine.push(Quarantined { ptr: ptr as usize, layout });
+    }
+}
@@ -0,0 +1,102 @@
+//! A size class (slab) allocator
+//!
+//! Small allocations are served from per-size caches of fixed size blocks,
+//! from 8 to 2048 bytes. A cache that runs empty takes a whole page from the
+//! backend and cuts it in blocks, freed blocks go back to the front of their
+//! cache. Both operations are O(1), unlike the linked list allocator whose
+//! allocations are linear in the number of holes.
+//!
+//! Anything bigger is rounded up to whole pages and given to the backend.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use spin::Mutex;
+
+/// The block sizes, every one must be a power of two so blocks are aligned on their size
+pub const BLOCK_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Size (and alignment) of the slabs taken from the backend, and granularity of large allocations
+pub const SLAB_SIZE: usize = 4096;
+
+/// Header written in every free block
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+const EMPTY: Option<&'static mut FreeBlock> = None;
+
+pub struct SlabAllocator<B: 'static> {
+    backend: &'static B,
+    caches: Mutex<[Option<&'static mut FreeBlock>; BLOCK_SIZES.len()]>,
+}
+
+impl<B: GlobalAlloc> SlabAllocator<B> {
+    pub const fn new(backend: &'static B) -> Self {
+        Self {
+            backend,
+            caches: Mutex::new([EMPTY; BLOCK_SIZES.len()]),
+        }
+    }
+
+    /// Takes a slab from the backend and splits it in blocks of `BLOCK_SIZES[index]`
+    ///
+    /// The first block is returned, the other ones are put in the cache.
+    unsafe fn refill(&self, cache: &mut Option<&'static mut FreeBlock>, index: usize) -> *mut u8 {
+        let slab = self.backend.alloc(Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE));
+        if slab.is_null() {
+            return null_mut();
+        }
+
+        let size = BLOCK_SIZES[index];
+        for offset in (size..SLAB_SIZE).step_by(size).rev() {
+            let block = slab.add(offset) as *mut FreeBlock;
+            block.write(FreeBlock { next: cache.take() });
+            *cache = Some(&mut *block);
+        }
+        slab
+    }
+}
+
+/// Index of the smallest block size fitting `layout`, `None` if it is too big
+fn cache_index(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= size)
+}
+
+/// Layout of a large allocation, rounded up to whole pages
+fn page_layout(layout: &Layout) -> Layout {
+    let size = (layout.size() + SLAB_SIZE - 1) & !(SLAB_SIZE - 1);
+    let align = layout.align().max(SLAB_SIZE);
+    unsafe { Layout::from_size_align_unchecked(size, align) }
+}
+
+unsafe impl<B: GlobalAlloc> GlobalAlloc for SlabAllocator<B> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match cache_index(&layout) {
+            Some(index) => {
+                let mut caches = self.caches.lock();
+                match caches[index].take() {
+                    Some(block) => {
+                        caches[index] = block.next.take();
+                        block as *mut FreeBlock as *mut u8
+                    }
+                    None => self.refill(&mut caches[index], index),
+                }
+            }
+            None => self.backend.alloc(page_layout(&layout)),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match cache_index(&layout) {
+            Some(index) => {
+                let mut caches = self.caches.lock();
+                let block = ptr as *mut FreeBlock;
+                block.write(FreeBlock { next: caches[index].take() });
+                caches[index] = Some(&mut *block);
+            }
+            None => self.backend.dealloc(ptr, page_layout(&layout)),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Heap statistics
+//!
+//! `Tracked` wraps the allocator actually doing the work and counts every
+//! allocation going through it. With the `heap_debug` feature it also keeps
+//! a table of the live allocations with the return addresses leading to them,
+//! so that leaks can be found (see `leaks_since`).
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of buckets of the size histogram, the first one counts allocations
+/// of 8 bytes or less, each bucket doubles the size and the last one counts
+/// everything bigger than 4KiB
+pub const HISTOGRAM_BUCKETS: usize = 11;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+/// Snapshot of the heap usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes currently allocated
+    pub allocated_bytes: usize,
+    /// Highest value `allocated_bytes` ever had
+    pub peak_bytes: usize,
+    /// Number of allocations not freed yet
+    pub live_allocations: usize,
+    pub total_allocations: usize,
+    pub total_frees: usize,
+    /// Allocations that returned null
+    pub failed_allocations: usize,
+    /// Number of allocations by size, see `HISTOGRAM_BUCKETS`
+    pub histogram: [usize; HISTOGRAM_BUCKETS],
+}
+
+impl HeapStats {
+    /// Upper bound (inclusive) of the sizes counted by the bucket `i`, `None` for the last one
+    pub fn bucket_limit(i: usize) -> Option<usize> {
+        if i + 1 < HISTOGRAM_BUCKETS { Some(8 << i) } else { None }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "heap: {} bytes in {} allocations (peak {} bytes)",
+            self.allocated_bytes, self.live_allocations, self.peak_bytes)?;
+        writeln!(f, "      {} allocations, {} frees, {} failures",
+            self.total_allocations, self.total_frees, self.failed_allocations)?;
+        for (i, count) in self.histogram.iter().enumerate() {
+            match HeapStats::bucket_limit(i) {
+                Some(limit) => writeln!(f, "  <= {:5}: {}", limit, count)?,
+                None => writeln!(f, "   > {:5}: {}", 8 << (i - 1), count)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bucket(size: usize) -> usize {
+    (0..HISTOGRAM_BUCKETS - 1)
+        .find(|&i| size <= 8 << i)
+        .unwrap_or(HISTOGRAM_BUCKETS - 1)
+}
+
+
+/// Allocator wrapper counting what goes through it
+pub struct Tracked<A: 'static> {
+    inner: &'static A,
+    allocated_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    live_allocations: AtomicUsize,
+    total_allocations: AtomicUsize,
+    total_frees: AtomicUsize,
+    failed_allocations: AtomicUsize,
+    histogram: [AtomicUsize; HISTOGRAM_BUCKETS],
+}
+
+impl<A: GlobalAlloc> Tracked<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        Self {
+            inner,
+            allocated_bytes: ZERO,
+            peak_bytes: ZERO,
+            live_allocations: ZERO,
+            total_allocations: ZERO,
+            total_frees: ZERO,
+            failed_allocations: ZERO,
+            histogram: [ZERO; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut histogram = [0; HISTOGRAM_BUCKETS];
+        for (count, counter) in histogram.iter_mut().zip(self.histogram.iter()) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+
+        HeapStats {
+            allocated_bytes: self.allocated_bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            live_allocations: self.live_allocations.load(Ordering::Relaxed),
+            total_allocations: self.total_allocations.load(Ordering::Relaxed),
+            total_frees: self.total_frees.load(Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+            histogram,
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if ptr.is_null() {
+            self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+            return ptr;
+        }
+
+        let size = layout.size();
+        let allocated = self.allocated_bytes.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes.fetch_max(allocated, Ordering::Relaxed);
+        self.live_allocations.fetch_add(1, Ordering::Relaxed);
+        self.total_allocations.fetch_add(1, Ordering::Relaxed);
+        self.histogram[bucket(size)].fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "heap_debug")]
+        debug::record(ptr, size);
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap_debug")]
+        debug::forget(ptr);
+
+        self.inner.dealloc(ptr, layout);
+        self.allocated_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.live_allocations.fetch_sub(1, Ordering::Relaxed);
+        self.total_frees.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+
+#[cfg(feature = "heap_debug")]
+pub use debug::{leak_mark, leaks_since, report_leaks_since, LeakMark, LiveAllocation};
+
+/// Table of the live allocations
+///
+/// Call sites are found by walking the frame pointers, which is why
+/// `.cargo/config.toml` forces them. The table has a fixed size because it
+/// obviously can't allocate, allocations that don't fit are only counted.
+#[cfg(feature = "heap_debug")]
+mod debug {
+    use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use spin::Mutex;
+
+    const TABLE_SIZE: usize = 4096;
+    /// Number of return addresses kept for every allocation
+    pub const BACKTRACE_DEPTH: usize = 4;
+    /// Frames skipped by the backtrace: `record`, `Tracked::alloc` and the `__rust_alloc` shims
+    const SKIPPED_FRAMES: usize = 3;
+
+    /// A point in the allocation history, see `leak_mark`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct LeakMark(u64);
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct LiveAllocation {
+        pub ptr: usize,
+        pub size: usize,
+        /// Return addresses, innermost first, 0 when the stack is shallower
+        pub backtrace: [usize; BACKTRACE_DEPTH],
+        sequence: u64,
+    }
+
+    static TABLE: Mutex<[Option<LiveAllocation>; TABLE_SIZE]> = Mutex::new([None; TABLE_SIZE]);
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    /// Allocations that didn't fit in the table
+    static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Returns a mark to be given to `leaks_since`
+    pub fn leak_mark() -> LeakMark {
+        LeakMark(SEQUENCE.load(Ordering::Relaxed))
+    }
+
+    /// Number of allocations made after `mark` and still alive
+    pub fn leaks_since(mark: LeakMark) -> usize {
+        TABLE.lock().iter().flatten().filter(|a| a.sequence >= mark.0).count()
+    }
+
+    /// Prints the allocations made after `mark` and still alive on the serial console
+    pub fn report_leaks_since(mark: LeakMark) {
+        let table = TABLE.lock();
+        for a in table.iter().flatten().filter(|a| a.sequence >= mark.0) {
+            crate::serial_println!("leak: {} bytes at {:#x}, allocated from {:x?}",
+                a.size, a.ptr, a.backtrace);
+        }
+        let untracked = UNTRACKED.load(Ordering::Relaxed);
+        if untracked != 0 {
+            crate::serial_println!("leak: {} allocations were not tracked (table full)", untracked);
+        }
+    }
+
+    pub(super) fn record(ptr: *mut u8, size: usize) {
+        let allocation = LiveAllocation {
+            ptr: ptr as usize,
+            size,
+            backtrace: backtrace(),
+            sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let mut table = TABLE.lock();
+        match table.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(allocation),
+            None => { UNTRACKED.fetch_add(1, Ordering::Relaxed); }
+        }
+    }
+
+    pub(super) fn forget(ptr: *mut u8) {
+        let mut table = TABLE.lock();
+        if let Some(slot) = table.iter_mut().find(|s| matches!(s, Some(a) if a.ptr == ptr as usize)) {
+            *slot = None;
+        }
+    }
+
+    /// Walks the saved frame pointers: `[rbp]` is the caller's rbp, `[rbp+8]` the return address
+    #[inline(always)]
+    fn backtrace() -> [usize; BACKTRACE_DEPTH] {
+        let mut addresses = [0; BACKTRACE_DEPTH];
+        let (mut rbp, rsp): (usize, usize);
+        unsafe { asm!("mov {}, rbp", "mov {}, rsp", out(reg) rbp, out(reg) rsp) };
+
+        for i in 0..SKIPPED_FRAMES + BACKTRACE_DEPTH {
+            // Stop on anything which doesn't look like a frame of the current stack
+            if rbp % 8 != 0 || rbp < rsp || rbp - rsp > 1024 * 1024 {
+                break;
+            }
+            let frame = rbp as *const usize;
+            let return_address = unsafe { *frame.add(1) };
+            if i >= SKIPPED_FRAMES {
+                addresses[i - SKIPPED_FRAMES] = return_address;
+            }
+            rbp = unsafe { *frame };
+        }
+        addresses
+    }
+}
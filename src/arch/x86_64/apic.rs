@@ -0,0 +1,314 @@
+//! Local APIC and I/O APIC
+//!
+//! See https://wiki.osdev.org/APIC and https://wiki.osdev.org/IOAPIC
+//!
+//! The local APIC of the CPU receives the interrupts and is told when they
+//! have been handled (EOI). The I/O APIC receives the IRQs of the devices
+//! and forwards them to a local APIC as vectors, following its redirection
+//! table. Both are programmed through memory mapped registers, reached here
+//! through the physical memory mapping.
+//!
+//! The ISA IRQs don't necessarily arrive on the I/O APIC pin of the same
+//! number: `ApicConfig` says where they are, from the ACPI MADT when there
+//! is one. Otherwise `ApicConfig::legacy` assumes the usual PC wiring (the
+//! PIT on pin 2, the others identity mapped).
+
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::{registers::model_specific::Msr, PhysAddr, VirtAddr};
+
+use crate::acpi::Madt;
+use crate::arch::port::Port;
+use crate::memory::physical_memory_offset;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Global enable bit of `IA32_APIC_BASE`
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Default physical address of the I/O APIC
+pub const DEFAULT_IO_APIC_ADDRESS: u64 = 0xfec0_0000;
+
+/// Vector the local APIC raises for spurious interrupts, its low 4 bits
+/// must be set on old APICs
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Whether the CPU has a local APIC (CPUID.01h:EDX[9])
+pub fn is_supported() -> bool {
+    let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
+    cpuid.edx & (1 << 9) != 0
+}
+
+/// Masks every IRQ of the 8259 PICs
+///
+/// The PICs must have been remapped before (see `interrupts::PICS`): a
+/// spurious IRQ can still be raised and must not look like an exception.
+pub fn disable_pics() {
+    unsafe {
+        Port::<u8>::new(0x21).write(0xff);
+        Port::<u8>::new(0xa1).write(0xff);
+    }
+}
+
+
+/// How an ISA IRQ is wired to the I/O APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaIrq {
+    /// Global system interrupt, the I/O APIC pin
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// Where the APICs are and how the ISA IRQs reach them
+#[derive(Debug, Clone, Copy)]
+pub struct ApicConfig {
+    pub local_apic: PhysAddr,
+    pub io_apic: PhysAddr,
+    /// First global system interrupt handled by the I/O APIC
+    pub io_apic_gsi_base: u32,
+    /// Indexed by ISA IRQ
+    pub isa_irqs: [IsaIrq; 16],
+}
+
+impl ApicConfig {
+    /// The usual PC configuration: the local APIC where `IA32_APIC_BASE`
+    /// says, the I/O APIC at its default address and the PIT on pin 2
+    pub fn legacy() -> Self {
+        let mut isa_irqs = [IsaIrq { gsi: 0, active_low: false, level_triggered: false }; 16];
+        for (irq, isa_irq) in isa_irqs.iter_mut().enumerate() {
+            isa_irq.gsi = irq as u32;
+        }
+        isa_irqs[0].gsi = 2;
+
+        ApicConfig {
+            local_apic: LocalApic::base_address(),
+            io_apic: PhysAddr::new(DEFAULT_IO_APIC_ADDRESS),
+            io_apic_gsi_base: 0,
+            isa_irqs,
+        }
+    }
+
+    /// The configuration described by the MADT, `None` if it has no I/O APIC
+    pub fn from_madt(madt: &Madt) -> Option<Self> {
+        let io_apic = madt.io_apic_for(0)?;
+
+        let mut isa_irqs = [IsaIrq { gsi: 0, active_low: false, level_triggered: false }; 16];
+        for (irq, isa_irq) in isa_irqs.iter_mut().enumerate() {
+            isa_irq.gsi = irq as u32;
+        }
+        for o in madt.overrides().filter(|o| (o.irq as usize) < isa_irqs.len()) {
+            isa_irqs[o.irq as usize] = IsaIrq {
+                gsi: o.gsi,
+                active_low: o.active_low,
+                level_triggered: o.level_triggered,
+            };
+        }
+
+        Some(ApicConfig {
+            local_apic: madt.local_apic,
+            io_apic: io_apic.address,
+            io_apic_gsi_base: io_apic.gsi_base,
+            isa_irqs,
+        })
+    }
+
+    /// From the ACPI tables if `acpi::init` found them, `legacy` otherwise
+    pub fn detect() -> Self {
+        crate::acpi::get()
+            .and_then(|acpi| acpi.madt.as_ref())
+            .and_then(ApicConfig::from_madt)
+            .unwrap_or_else(ApicConfig::legacy)
+    }
+}
+
+
+/// Virtual address of the registers at `phys`
+fn mmio(phys: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + phys.as_u64()
+}
+
+/// Registers of the local APIC, offsets from its base
+mod lapic {
+    pub const ID: usize = 0x20;
+    pub const VERSION: usize = 0x30;
+    pub const TASK_PRIORITY: usize = 0x80;
+    pub const EOI: usize = 0xb0;
+    pub const SPURIOUS: usize = 0xf0;
+    pub const LVT_TIMER: usize = 0x320;
+    pub const LVT_LINT0: usize = 0x350;
+    pub const LVT_ERROR: usize = 0x370;
+
+    /// APIC software enable bit of the spurious interrupt register
+    pub const SOFTWARE_ENABLE: u32 = 1 << 8;
+    /// Mask bit of the local vector table entries
+    pub const LVT_MASKED: u32 = 1 << 16;
+}
+
+/// The local APIC of the current CPU
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    /// Physical address of the registers, from `IA32_APIC_BASE`
+    pub fn base_address() -> PhysAddr {
+        let base = unsafe { Msr::new(IA32_APIC_BASE).read() };
+        PhysAddr::new(base & 0x000f_ffff_ffff_f000)
+    }
+
+    /// The local APIC whose registers are at `phys`
+    ///
+    /// This function is unsafe because the caller must guarantee that `phys`
+    /// is the address of the local APIC, and that the physical memory is
+    /// mapped (`memory::init` was called).
+    pub unsafe fn new(phys: PhysAddr) -> Self {
+        LocalApic { base: mmio(phys) }
+    }
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        read_volatile((self.base.as_u64() as usize + reg) as *const u32)
+    }
+
+    unsafe fn write(&mut self, reg: usize, value: u32) {
+        write_volatile((self.base.as_u64() as usize + reg) as *mut u32, value);
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(lapic::ID) } >> 24) as u8
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(lapic::VERSION) as u8 }
+    }
+
+    /// Enables the APIC, spurious interrupts are raised on `spurious_vector`
+    ///
+    /// The local interrupts (its timer, LINT0 where the PIC is wired and
+    /// errors) are masked, nothing is filtered by priority.
+    pub fn enable(&mut self, spurious_vector: u8) {
+        unsafe {
+            let mut msr = Msr::new(IA32_APIC_BASE);
+            msr.write(msr.read() | APIC_BASE_ENABLE);
+
+            self.write(lapic::LVT_TIMER, lapic::LVT_MASKED);
+            self.write(lapic::LVT_LINT0, lapic::LVT_MASKED);
+            self.write(lapic::LVT_ERROR, lapic::LVT_MASKED);
+            self.write(lapic::TASK_PRIORITY, 0);
+            self.write(lapic::SPURIOUS, lapic::SOFTWARE_ENABLE | spurious_vector as u32);
+        }
+    }
+
+    /// Signals the end of the interrupt being handled
+    pub fn end_of_interrupt(&mut self) {
+        unsafe { self.write(lapic::EOI, 0) };
+    }
+}
+
+
+/// Registers of the I/O APIC, selected through `IOREGSEL`
+mod ioapic {
+    pub const IOREGSEL: usize = 0x00;
+    pub const IOWIN: usize = 0x10;
+
+    pub const ID: u32 = 0x00;
+    pub const VERSION: u32 = 0x01;
+    /// First of the redirection table entries, each is two registers
+    pub const REDIRECTION_TABLE: u32 = 0x10;
+}
+
+/// An entry of the redirection table, fixed delivery to a single APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redirection {
+    pub vector: u8,
+    /// APIC id of the destination
+    pub destination: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+    pub masked: bool,
+}
+
+impl Redirection {
+    fn to_raw(self) -> u64 {
+        self.vector as u64
+            | (self.active_low as u64) << 13
+            | (self.level_triggered as u64) << 15
+            | (self.masked as u64) << 16
+            | (self.destination as u64) << 56
+    }
+
+    fn from_raw(raw: u64) -> Self {
+        Redirection {
+            vector: raw as u8,
+            destination: (raw >> 56) as u8,
+            active_low: raw & (1 << 13) != 0,
+            level_triggered: raw & (1 << 15) != 0,
+            masked: raw & (1 << 16) != 0,
+        }
+    }
+}
+
+/// An I/O APIC
+pub struct IoApic {
+    base: VirtAddr,
+}
+
+impl IoApic {
+    /// The I/O APIC whose registers are at `phys`
+    ///
+    /// This function is unsafe for the same reasons as `LocalApic::new`.
+    pub unsafe fn new(phys: PhysAddr) -> Self {
+        IoApic { base: mmio(phys) }
+    }
+
+    unsafe fn read(&self, reg: u32) -> u32 {
+        let base = self.base.as_u64() as usize;
+        write_volatile((base + ioapic::IOREGSEL) as *mut u32, reg);
+        read_volatile((base + ioapic::IOWIN) as *const u32)
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        let base = self.base.as_u64() as usize;
+        write_volatile((base + ioapic::IOREGSEL) as *mut u32, reg);
+        write_volatile((base + ioapic::IOWIN) as *mut u32, value);
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(ioapic::ID) } >> 24) as u8 & 0xf
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(ioapic::VERSION) as u8 }
+    }
+
+    /// Number of pins, i.e. of entries of the redirection table
+    pub fn pins(&self) -> u32 {
+        ((unsafe { self.read(ioapic::VERSION) } >> 16) & 0xff) + 1
+    }
+
+    pub fn redirection(&self, pin: u32) -> Redirection {
+        assert!(pin < self.pins(), "no I/O APIC pin {}", pin);
+        let reg = ioapic::REDIRECTION_TABLE + 2 * pin;
+        let raw = unsafe { self.read(reg) as u64 | (self.read(reg + 1) as u64) << 32 };
+        Redirection::from_raw(raw)
+    }
+
+    pub fn set_redirection(&mut self, pin: u32, redirection: Redirection) {
+        assert!(pin < self.pins(), "no I/O APIC pin {}", pin);
+        let reg = ioapic::REDIRECTION_TABLE + 2 * pin;
+        let raw = redirection.to_raw();
+        unsafe {
+            // Masked while it is half written
+            self.write(reg, 1 << 16);
+            self.write(reg + 1, (raw >> 32) as u32);
+            self.write(reg, raw as u32);
+        }
+    }
+
+    /// Masks every pin
+    pub fn mask_all(&mut self) {
+        for pin in 0..self.pins() {
+            let redirection = self.redirection(pin);
+            self.set_redirection(pin, Redirection { masked: true, ..redirection });
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod apic;
 pub mod instructions;
 pub mod pit;
+pub mod speaker;
@@ -1,13 +1,17 @@
 //! See https://wiki.osdev.org/Programmable_Interval_Timer#Outputs
-//! 
-//! The maths: 
+//!
+//! The maths:
 //! The nominal frequency `nf` is 1.193182 Mhz
 //! The reload value `rl` set specify how often an interrupt will be launched,
-//! The resulting frequency is `nf`/rl, for a delay between interrupts of rl/`nf`*1000 ms 
-//! 
-//! To get a reload from a specified frequency 
+//! The resulting frequency is `nf`/rl, for a delay between interrupts of rl/`nf`*1000 ms
+//!
+//! To get a reload from a specified frequency `f`, rl = `nf`/f, and from a
+//! delay `d` in µs, rl = `nf`/1000000*d. Both are computed in 32.32 fixed
+//! point and rounded to the nearest integer, the target has no FPU.
+//! A reload of 0 stands for 65536.
 
 use crate::arch::port::*;
+use crate::utils::fixed_point::FixedPoint32_32;
 
 const CH0_DATA: Port<u8> = Port::new(0x40);
 const CH1_DATA: Port<u8> = Port::new(0x41);
@@ -15,18 +19,24 @@ const CH2_DATA: Port<u8> = Port::new(0x42);
 const MODE_PORT: Port<u8> = Port::new(0x43);
 
 /// The nominal frequency of the PIT in hertz
-const NOMINAL_FREQUENCY : f64 = 1_193_182 as f64;
-const MINIMAL_FREQUENCY : f64 = NOMINAL_FREQUENCY / (u16::MAX as f64 + 1 as f64);
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// The largest reload value, written as 0
+const MAX_RELOAD: u32 = u16::MAX as u32 + 1;
+/// The lowest frequency in hertz, 18.2 Hz rounded up
+pub const MINIMAL_FREQUENCY: u32 = (PIT_FREQUENCY + MAX_RELOAD - 1) / MAX_RELOAD;
+/// The longest delay in µs, about 55 ms
+pub const MAXIMAL_DELAY: u32 = (MAX_RELOAD as u64 * 1_000_000 / PIT_FREQUENCY as u64) as u32;
+
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
 pub enum SelectChannel {
     Channel0 = 0x00,
     Channel1 = 0x40,
     Channel2 = 0x80,
-    ReadBack = 0x0c,
+    ReadBack = 0xc0,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum AccessMode {
     LatchCountValue = 0x00,
@@ -35,7 +45,7 @@ pub enum AccessMode {
     LobyteHibyte = 0x30,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OperatingMode {
     InterruptOnTerminalCount= 0x00 ,
@@ -47,6 +57,21 @@ pub enum OperatingMode {
     // RateGenerator (again)
     // SquareWaveGenerator (again)
 }
+
+impl OperatingMode {
+    /// Decodes the mode bits of a command or a status byte
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x0e {
+            0x00 => OperatingMode::InterruptOnTerminalCount,
+            0x02 => OperatingMode::HardawareOneShot,
+            0x04 | 0x0c => OperatingMode::RateGenerator,
+            0x06 | 0x0e => OperatingMode::SquareWaveGenerator,
+            0x08 => OperatingMode::SoftwareTriggeredStrobe,
+            _ => OperatingMode::HardwareTriggeredStrobe,
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq)]
 pub struct ModeCommand(u8);
 impl ModeCommand {
@@ -55,21 +80,56 @@ impl ModeCommand {
     }
 }
 
+/// Read-back command bit: don't latch the count
+const READ_BACK_NO_COUNT: u8 = 0x20;
+
+/// The status of a channel, given by the read-back command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Status(pub u8);
+
+impl Status {
+    /// State of the output pin, which raises IRQ 0 on channel 0
+    pub fn output(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// The reload value written wasn't loaded in the counter yet
+    pub fn null_count(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    pub fn access_mode(&self) -> AccessMode {
+        match self.0 & 0x30 {
+            0x10 => AccessMode::LobyteOnly,
+            0x20 => AccessMode::HibyteOnly,
+            0x30 => AccessMode::LobyteHibyte,
+            _ => AccessMode::LatchCountValue,
+        }
+    }
+
+    pub fn operating_mode(&self) -> OperatingMode {
+        OperatingMode::from_bits(self.0)
+    }
+
+    /// The counter counts in BCD rather than in binary
+    pub fn bcd(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+}
+
 pub struct Channel {
     /// The port linked to this channel
     port: Port<u8>,
     channel: SelectChannel,
-    /// The last ModeCommand sent
-    mode: ModeCommand,
+    /// The last ModeCommand sent, None if unknown
+    mode: Option<ModeCommand>,
     reload_value: Option<u16>
 }
 
-use crate::println;
-
 impl Channel {
 
-    /// Create a new channel struct by channel number 
-    /// 
+    /// Create a new channel struct by channel number
+    ///
     /// Safety:
     /// Unsafe because the callee must ensure that the port is not written
     /// to anywhere else, because it assumes modes are not changed between calls
@@ -82,45 +142,65 @@ impl Channel {
         };
 
         Self {
-            port, 
+            port,
             channel,
-            mode: ModeCommand(0),
+            mode: None,
             reload_value: None,
-            
+
         }
     }
 
+    /// The number of the channel, 0 to 2
+    pub fn number(&self) -> u8 {
+        self.channel as u8 >> 6
+    }
 
+    /// Calculate a reload value given a delay in µs and set it
+    ///
+    /// In `InterruptOnTerminalCount` mode the output goes up once, after
+    /// `delay`: a one-shot timer, armed again by calling this again.
     pub fn set_delay(&mut self, om:OperatingMode, delay:u32){
-        todo!()        
+        assert!(delay > 0, "Delay is too short");
+        assert!(delay <= MAXIMAL_DELAY, "Delay is too long");
+
+        let cycles_per_us = FixedPoint32_32::from((PIT_FREQUENCY, 0)) / 1_000_000;
+        // 65536 is truncated to 0 as expected
+        let rl = (cycles_per_us * delay).round().max(1);
+        self.set_reload_value(om, rl as u16);
     }
 
     /// Calculate a reload value given a frequency in hertz and set it
-    /// 
-    /// Also fuck it we use floating points
-    pub fn set_frequency(&mut self, om:OperatingMode, freq: f64){
+    pub fn set_frequency(&mut self, om:OperatingMode, freq: u32){
 
-        assert!(freq > MINIMAL_FREQUENCY as f64, "Frequency is too low");
-        assert!(freq < NOMINAL_FREQUENCY as f64, "Frequency is too high");
-        // We calculate the reload value 
-        let rl = (NOMINAL_FREQUENCY / freq) as u16;
-        //let remainder = NOMINAL_FREQUENCY % freq;
-        //if remainder > freq / 2 { rl += 1; }
+        assert!(freq >= MINIMAL_FREQUENCY, "Frequency is too low");
+        assert!(freq <= PIT_FREQUENCY, "Frequency is too high");
+        // We calculate the reload value
+        let rl = (FixedPoint32_32::from((PIT_FREQUENCY, 0)) / freq).round();
 
         self.set_reload_value(om, rl as u16);
-        
+
+    }
+
+    /// The last reload value set, 0 stands for 65536
+    pub fn reload_value(&self) -> Option<u16> {
+        self.reload_value
     }
 
-    /// Sets the reload the value and 
+    /// Sets the reload the value and the operating mode
     pub fn set_reload_value(&mut self, om: OperatingMode, mut rl: u16){
+        // 1 is illegal in the periodic modes, and would become 0 (65536) below
+        if rl == 1 && (om == OperatingMode::RateGenerator || om == OperatingMode::SquareWaveGenerator) {
+            rl = 2;
+        }
+        // On square wave generator we make sure the rl is pair
+        if let OperatingMode::SquareWaveGenerator = om {
+            rl &= !1; // Branchless programing !
+        }
         self.reload_value = Some(rl);
-        
-        println!("rl set to {}, real freq {}Hz, delay {}ms", rl, NOMINAL_FREQUENCY as f64 / rl as f64, rl as f64 / NOMINAL_FREQUENCY as f64 * 1000 as f64);
-        
 
-        // On square wave generator we make sure the rl is pair 
-        if let OperatingMode::SquareWaveGenerator = om {
-            rl &= 0xFE; // Branchless programing !
+        // In one-shot mode the output only goes low again on a mode command
+        if let OperatingMode::InterruptOnTerminalCount = om {
+            self.mode = None;
         }
         unsafe {
             self.set_mode(AccessMode::LobyteHibyte, om);
@@ -128,16 +208,21 @@ impl Channel {
         }
     }
 
+    /// Sends the mode command, unless it is the one in use already
+    ///
+    /// Safety:
+    /// Changing the access mode changes how the data port must be used
     pub unsafe fn set_mode(&mut self, am: AccessMode, om: OperatingMode){
         let mode = ModeCommand::new(self.channel, am, om);
-        if self.mode != mode {
-            MODE_PORT.write(self.mode.0);
+        if self.mode.as_ref() != Some(&mode) {
+            MODE_PORT.write(mode.0);
+            self.mode = Some(mode);
         }
     }
 
     /// Sets the reload value of the specified channel
-    /// 
-    /// Safety: 
+    ///
+    /// Safety:
     /// unsafe because it is expected that the channel
     /// is in lobyte/hibyte access mode
     pub unsafe fn send_reload_value(&self, rl: u16){
@@ -145,8 +230,26 @@ impl Channel {
         self.port.write(rl as u8);
         self.port.write((rl >> 8) as u8);
     }
-}
-
-
 
+    /// Latches the current count and reads it
+    ///
+    /// The channel is expected to be in lobyte/hibyte access mode, which the
+    /// setters use.
+    pub fn read_count(&self) -> u16 {
+        unsafe {
+            MODE_PORT.write(self.channel as u8 | AccessMode::LatchCountValue as u8);
+            let lo = self.port.read() as u16;
+            let hi = self.port.read() as u16;
+            (hi << 8) | lo
+        }
+    }
 
+    /// Reads the status of the channel with the read-back command
+    pub fn read_status(&self) -> Status {
+        let select = 1 << (self.number() + 1);
+        unsafe {
+            MODE_PORT.write(SelectChannel::ReadBack as u8 | READ_BACK_NO_COUNT | select);
+            Status(self.port.read())
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! The PC speaker, driven by channel 2 of the PIT
+//!
+//! See https://wiki.osdev.org/PC_Speaker
+//!
+//! Channel 2 generates a square wave at the frequency of the tone. Port
+//! 0x61 gates the channel (bit 0) and connects its output to the speaker
+//! (bit 1), the other bits belong to the keyboard controller and are kept.
+//!
+//! A frequency of 0 is a rest: the speaker is silent.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::pit::{Channel, OperatingMode, MINIMAL_FREQUENCY, PIT_FREQUENCY};
+use crate::arch::port::Port;
+use crate::time::{sleep, Duration};
+
+const SPEAKER_PORT: Port<u8> = Port::new(0x61);
+/// Channel 2 counts while its gate is up
+const GATE: u8 = 1 << 0;
+/// The output of channel 2 drives the speaker
+const SPEAKER_DATA: u8 = 1 << 1;
+
+static CHANNEL2: Mutex<Channel> = Mutex::new(unsafe { Channel::new(2) });
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeakerError {
+    /// Channel 2 can't generate the frequency, from 19 Hz to 1.19 MHz
+    FrequencyOutOfRange(u32),
+}
+
+fn update_port(f: impl FnOnce(u8) -> u8) {
+    unsafe {
+        let value = SPEAKER_PORT.read();
+        SPEAKER_PORT.write(f(value));
+    }
+}
+
+/// Plays a tone of `frequency` hertz until `stop` is called
+pub fn play(frequency: u32) -> Result<(), SpeakerError> {
+    if frequency == 0 {
+        stop();
+        return Ok(());
+    }
+    if !(MINIMAL_FREQUENCY..=PIT_FREQUENCY).contains(&frequency) {
+        return Err(SpeakerError::FrequencyOutOfRange(frequency));
+    }
+
+    without_interrupts(|| {
+        CHANNEL2.lock().set_frequency(OperatingMode::SquareWaveGenerator, frequency);
+        update_port(|value| value | GATE | SPEAKER_DATA);
+    });
+    Ok(())
+}
+
+pub fn stop() {
+    without_interrupts(|| update_port(|value| value & !(GATE | SPEAKER_DATA)));
+}
+
+/// Whether the speaker is connected to channel 2 and the channel counts
+pub fn is_playing() -> bool {
+    unsafe { SPEAKER_PORT.read() & (GATE | SPEAKER_DATA) == GATE | SPEAKER_DATA }
+}
+
+/// The frequency of the last tone played, rounded
+pub fn frequency() -> Option<u32> {
+    let reload = CHANNEL2.lock().reload_value()?;
+    let reload = if reload == 0 { 1 << 16 } else { reload as u32 };
+    Some((PIT_FREQUENCY + reload / 2) / reload)
+}
+
+/// Stops the speaker when dropped, in case the future playing is dropped
+struct Playing;
+
+impl Drop for Playing {
+    fn drop(&mut self) {
+        stop();
+    }
+}
+
+/// Plays a tone of `frequency` hertz for `duration`
+pub async fn beep(frequency: u32, duration: Duration) -> Result<(), SpeakerError> {
+    play(frequency)?;
+    let _playing = Playing;
+    sleep(duration).await;
+    Ok(())
+}
+
+
+/// A note of a tune, a rest if the frequency is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub frequency: u32,
+    pub duration: Duration,
+}
+
+impl Note {
+    pub const fn new(frequency: u32, millis: u64) -> Note {
+        Note { frequency, duration: Duration::from_millis(millis) }
+    }
+
+    pub const fn rest(millis: u64) -> Note {
+        Note::new(0, millis)
+    }
+}
+
+/// Frequencies of the fourth octave, in hertz
+pub mod pitch {
+    pub const C4: u32 = 262;
+    pub const D4: u32 = 294;
+    pub const E4: u32 = 330;
+    pub const F4: u32 = 349;
+    pub const G4: u32 = 392;
+    pub const A4: u32 = 440;
+    pub const B4: u32 = 494;
+    pub const C5: u32 = 523;
+}
+
+/// Silence between two notes, so that repeated notes are heard apart
+const NOTE_GAP: Duration = Duration::from_millis(10);
+
+/// Plays `notes` one after the other, stops at the first one out of range
+pub async fn play_notes(notes: &[Note]) -> Result<(), SpeakerError> {
+    for note in notes {
+        beep(note.frequency, note.duration).await?;
+        if note.frequency != 0 {
+            sleep(NOTE_GAP).await;
+        }
+    }
+    Ok(())
+}
@@ -5,21 +5,29 @@ use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 use x86_64::structures::gdt::SegmentSelector;
 
+use conquer_once::spin::OnceCell;
 use lazy_static::lazy_static;
 
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of the IST stacks allocated by `install_ist_stacks`
+pub const IST_STACK_SIZE: usize = 4096 * 5;
+
 
 pub fn init() {
+    load(&GDT);
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
     use x86_64::instructions::segmentation::set_cs;
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.0.load();
 
     unsafe {
-        set_cs(GDT.1.cs);
-        load_tss(GDT.1.tss);
+        set_cs(gdt.1.cs);
+        load_tss(gdt.1.tss);
     }
 }
 
@@ -29,24 +37,27 @@ struct Selectors {
     tss: SegmentSelector
 }
 
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let cs = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            cs,
+            tss,
+        },
+    )
+}
+
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let cs = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                cs,
-                tss,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
 }
 
 
 lazy_static! {
+    /// Used until the memory is set up, double faults use a static stack
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
@@ -60,3 +71,32 @@ lazy_static! {
         tss
     };
 }
+
+
+/// The TSS and GDT loaded by `install_ist_stacks`
+static KERNEL_TSS: OnceCell<TaskStateSegment> = OnceCell::uninit();
+static KERNEL_GDT: OnceCell<(GlobalDescriptorTable, Selectors)> = OnceCell::uninit();
+
+/// Replaces the early static IST stacks with kernel stacks, which have a guard page
+///
+/// A new TSS holding the stacks is loaded along with its own GDT, the early
+/// TSS is left alone. Must be called once, after the kernel memory is
+/// installed.
+pub fn install_ist_stacks() {
+    use crate::memory::KernelStack;
+
+    KERNEL_TSS.try_init_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = KernelStack::new(IST_STACK_SIZE)
+            .expect("failed to allocate the double fault stack")
+            .leak();
+        tss
+    }).expect("IST stacks already installed");
+    let tss = KERNEL_TSS.try_get().unwrap();
+    let _ = KERNEL_GDT.try_init_once(|| new_gdt(tss));
+
+    // Interrupts must not see the GDT and the TSS half replaced
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        load(KERNEL_GDT.try_get().unwrap());
+    });
+}
@@ -0,0 +1,365 @@
+//! CPU exceptions
+//!
+//! Every architecturally defined exception has a handler. Traps (debug,
+//! breakpoint, overflow) and NMIs are reported and execution goes on,
+//! page faults first go through the copy-on-write and demand paging code.
+//! Any other exception prints an `ExceptionReport` and the faulting frame,
+//! then panics: there is nothing to return to. Machine checks halt instead,
+//! like NMIs and debug exceptions they can interrupt the code holding the
+//! VGA writer and only print when it is free.
+//!
+//! `recover_next` lets the kernel tests trigger a fault on purpose and
+//! resume after the faulting instruction, `take_last_report` tells them how
+//! the fault was reported.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+use super::stats;
+use crate::{gdt, hlt_loop, println};
+use crate::vga_buffer::try_print;
+
+/// Mnemonic and name of the exception vectors, `None` for the reserved ones
+const EXCEPTIONS: [Option<(&str, &str)>; 32] = [
+    Some(("#DE", "divide error")),
+    Some(("#DB", "debug")),
+    Some(("NMI", "non maskable interrupt")),
+    Some(("#BP", "breakpoint")),
+    Some(("#OF", "overflow")),
+    Some(("#BR", "bound range exceeded")),
+    Some(("#UD", "invalid opcode")),
+    Some(("#NM", "device not available")),
+    Some(("#DF", "double fault")),
+    None,
+    Some(("#TS", "invalid TSS")),
+    Some(("#NP", "segment not present")),
+    Some(("#SS", "stack segment fault")),
+    Some(("#GP", "general protection fault")),
+    Some(("#PF", "page fault")),
+    None,
+    Some(("#MF", "x87 floating point exception")),
+    Some(("#AC", "alignment check")),
+    Some(("#MC", "machine check")),
+    Some(("#XM", "SIMD floating point exception")),
+    Some(("#VE", "virtualization exception")),
+    None, None, None, None, None, None, None, None, None,
+    Some(("#SX", "security exception")),
+    None,
+];
+
+/// Mnemonic and name of the exception `vector`
+pub fn exception_name(vector: u8) -> Option<(&'static str, &'static str)> {
+    EXCEPTIONS.get(vector as usize).copied().flatten()
+}
+
+
+/// Which descriptor table a selector error code refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// Error code of the exceptions related to a segment selector: #TS, #NP,
+/// #SS and #GP
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode(pub u64);
+
+impl SelectorErrorCode {
+    /// The exception happened while delivering an event external to the program
+    pub fn external(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        match (self.0 >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b01 | 0b11 => DescriptorTable::Idt,
+            _ => DescriptorTable::Ldt,
+        }
+    }
+
+    /// Index of the descriptor in its table
+    pub fn index(&self) -> u16 {
+        ((self.0 >> 3) & 0x1fff) as u16
+    }
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectorErrorCode")
+            .field("external", &self.external())
+            .field("table", &self.table())
+            .field("index", &self.index())
+            .finish()
+    }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}[{:#x}]", self.table(), self.index())?;
+        if self.external() {
+            write!(f, " (external)")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// What an exception handler saw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionReport {
+    pub vector: u8,
+    pub instruction_pointer: VirtAddr,
+    pub code_segment: u64,
+    pub stack_pointer: VirtAddr,
+    pub error_code: Option<u64>,
+}
+
+impl ExceptionReport {
+    fn new(vector: u8, stack_frame: &InterruptStackFrame, error_code: Option<u64>) -> Self {
+        ExceptionReport {
+            vector,
+            instruction_pointer: stack_frame.instruction_pointer,
+            code_segment: stack_frame.code_segment,
+            stack_pointer: stack_frame.stack_pointer,
+            error_code,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        exception_name(self.vector).map_or("??", |(mnemonic, _)| mnemonic)
+    }
+
+    pub fn name(&self) -> &'static str {
+        exception_name(self.vector).map_or("reserved exception", |(_, name)| name)
+    }
+
+    /// The error code decoded, for the exceptions caused by a selector
+    ///
+    /// A #GP not caused by a segment has an error code of 0, hence no selector.
+    pub fn selector(&self) -> Option<SelectorErrorCode> {
+        match (self.vector, self.error_code) {
+            (10..=13, Some(code)) if code != 0 => Some(SelectorErrorCode(code)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) at {:#x}", self.name(), self.mnemonic(), self.instruction_pointer.as_u64())?;
+        if let Some(selector) = self.selector() {
+            write!(f, ", selector {}", selector)?;
+        } else if let Some(code) = self.error_code {
+            write!(f, ", error code {:#x}", code)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Number of bytes to skip on the next fault, 0 when a fault is fatal
+static RECOVERY: AtomicU64 = AtomicU64::new(0);
+static LAST_REPORT: Mutex<Option<ExceptionReport>> = Mutex::new(None);
+
+/// Makes the next fault resume `length` bytes after the faulting
+/// instruction, where the handler would otherwise panic
+///
+/// Meant for the tests, `length` should be the length of the faulting
+/// instruction.
+pub fn recover_next(length: u64) {
+    RECOVERY.store(length, Ordering::SeqCst);
+}
+
+/// The report of the last exception, which is forgotten
+pub fn take_last_report() -> Option<ExceptionReport> {
+    LAST_REPORT.lock().take()
+}
+
+/// Records the report, the exception is one we can return from
+fn report(vector: u8, stack_frame: &InterruptStackFrame, error_code: Option<u64>) -> ExceptionReport {
+    stats::count(vector);
+    let report = ExceptionReport::new(vector, stack_frame, error_code);
+    if let Some(mut last) = LAST_REPORT.try_lock() {
+        *last = Some(report);
+    }
+    report
+}
+
+/// Handles a fault after which the faulting instruction can't be restarted
+fn fault(vector: u8, stack_frame: &mut InterruptStackFrame, error_code: Option<u64>) {
+    let report = report(vector, stack_frame, error_code);
+
+    let skip = RECOVERY.swap(0, Ordering::SeqCst);
+    if skip != 0 {
+        unsafe { stack_frame.as_mut().instruction_pointer += skip };
+        return;
+    }
+
+    println!("exception: {}", report);
+    print_isf(stack_frame);
+    panic!("unable to resume");
+}
+
+/// Handlers which only call `fault`
+macro_rules! fault_handlers {
+    ($($name:ident => $vector:expr),* $(,)?) => {$(
+        extern "x86-interrupt" fn $name(stack_frame: &mut InterruptStackFrame) {
+            fault($vector, stack_frame, None);
+        }
+    )*};
+    ($($name:ident => $vector:expr, error_code),* $(,)?) => {$(
+        extern "x86-interrupt" fn $name(stack_frame: &mut InterruptStackFrame, error_code: u64) {
+            fault($vector, stack_frame, Some(error_code));
+        }
+    )*};
+}
+
+fault_handlers! {
+    divide_error_handler => 0,
+    bound_range_exceeded_handler => 5,
+    invalid_opcode_handler => 6,
+    device_not_available_handler => 7,
+    x87_floating_point_handler => 16,
+    simd_floating_point_handler => 19,
+    virtualization_handler => 20,
+}
+
+fault_handlers! {
+    invalid_tss_handler => 10, error_code,
+    segment_not_present_handler => 11, error_code,
+    stack_segment_fault_handler => 12, error_code,
+    general_protection_fault_handler => 13, error_code,
+    alignment_check_handler => 17, error_code,
+    security_exception_handler => 30, error_code,
+}
+
+
+/// Sets the handlers of all the exceptions in `idt`
+pub fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault
+           .set_handler_fn(double_fault_handler)
+           .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+
+// Debug exceptions, NMIs and machine checks can interrupt code holding the
+// VGA writer, even with interrupts disabled: they only print if it is free
+
+extern "x86-interrupt" fn debug_handler(
+    stack_frame: &mut InterruptStackFrame)
+{
+    let report = report(1, stack_frame, None);
+    try_print(format_args!("exception: {}\n", report));
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(
+    stack_frame: &mut InterruptStackFrame)
+{
+    let report = report(2, stack_frame, None);
+    try_print(format_args!("exception: {}\n", report));
+}
+
+extern "x86-interrupt" fn breakpoint_handler(
+    stack_frame: &mut InterruptStackFrame)
+{
+    report(3, stack_frame, None);
+    println!("Breakpoint reached \n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn overflow_handler(
+    stack_frame: &mut InterruptStackFrame)
+{
+    println!("exception: {}", report(4, stack_frame, None));
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    stats::count(14);
+    // Shared pages (see memory::cow) and lazily backed memory (see memory::demand)
+    let address = Cr2::read();
+    if crate::memory::cow::handle_page_fault(address, error_code)
+        || crate::memory::demand::handle_page_fault(address, error_code)
+    {
+        return;
+    }
+
+    println!("exception: page fault");
+    println!("Accessed Address: {:?}", Cr2::read());
+    if let Some(diagnosis) = crate::memory::protect::diagnose(address, error_code) {
+        println!("Protection violation: {}", diagnosis);
+    }
+    println!("Error Code: {:?}", error_code);
+    print_isf(stack_frame);
+    hlt_loop();
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: &mut InterruptStackFrame, _error_code: u64) -> !
+{
+    use x86_64::registers::control::Cr2;
+
+    stats::count(8);
+    println!("Exception: double fault error : ");
+    let address = Cr2::read();
+    if crate::memory::stack::is_guard_page(address) {
+        println!("kernel stack overflow: guard page hit at {:?}", address);
+    }
+    print_isf(stack_frame);
+    panic!("unable to resume");
+}
+
+extern "x86-interrupt" fn machine_check_handler(
+    stack_frame: &mut InterruptStackFrame) -> !
+{
+    let report = report(18, stack_frame, None);
+    try_print(format_args!("exception: {}\n", report));
+    // Not a panic, the panic handler prints with `println`
+    x86_64::instructions::interrupts::disable();
+    hlt_loop();
+}
+
+
+
+// Fast patch because it seems that a panic occurs while printing it
+pub fn print_isf(sf: &InterruptStackFrame)  {
+    println!("ExceptionStackFrame {{
+    instruction_pointer: 0x{:x?},
+    code_segment: {},
+    cpu_flags: {:x?},
+    stack_pointer: 0x{:x?},
+    stack_segment: {}
+    }}", sf.instruction_pointer.as_u64(), sf.code_segment, sf.cpu_flags, sf.stack_pointer.as_u64(), sf.stack_segment);
+
+}
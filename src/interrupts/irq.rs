@@ -0,0 +1,215 @@
+//! Registration of IRQ handlers
+//!
+//! Each of the 16 ISA IRQ lines has a stub in the IDT, at `PIC_1_OFFSET +
+//! line`, which calls the handlers registered on the line and then signals
+//! the end of interrupt to the right controller. A line can be shared by
+//! `MAX_SHARED_HANDLERS` handlers, every one of them is called. Lines are
+//! masked as long as nobody handles them.
+//!
+//! The handlers are kept in atomic slots so that the stubs never take a lock.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use super::{controller, stats, Controller, PIC_1_OFFSET};
+use crate::arch::port::Port;
+
+/// Number of ISA IRQ lines
+pub const IRQ_LINES: u8 = 16;
+
+/// Maximum number of handlers sharing a line
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// The line the slave PIC is cascaded on, never handed out
+pub const CASCADE_LINE: u8 = 2;
+
+/// A handler is told which line raised the interrupt, it must not block
+pub type IrqHandler = fn(line: u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// Not an ISA IRQ line, or the cascade
+    InvalidLine,
+    /// `MAX_SHARED_HANDLERS` handlers are registered on the line already
+    TooManyHandlers,
+    AlreadyRegistered,
+    NotRegistered,
+}
+
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+const EMPTY_LINE: [AtomicUsize; MAX_SHARED_HANDLERS] = [EMPTY; MAX_SHARED_HANDLERS];
+
+/// The handlers as `usize`, 0 for an empty slot
+static HANDLERS: [[AtomicUsize; MAX_SHARED_HANDLERS]; IRQ_LINES as usize] = [EMPTY_LINE; IRQ_LINES as usize];
+
+fn check_line(line: u8) -> Result<&'static [AtomicUsize; MAX_SHARED_HANDLERS], IrqError> {
+    if line >= IRQ_LINES || line == CASCADE_LINE {
+        return Err(IrqError::InvalidLine);
+    }
+    Ok(&HANDLERS[line as usize])
+}
+
+/// Adds `handler` to the handlers of `line`, which is unmasked
+pub fn register_irq(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    let slots = check_line(line)?;
+    let handler = handler as usize;
+    if slots.iter().any(|slot| slot.load(Ordering::Acquire) == handler) {
+        return Err(IrqError::AlreadyRegistered);
+    }
+
+    let registered = slots.iter().any(|slot| {
+        slot.compare_exchange(0, handler, Ordering::AcqRel, Ordering::Acquire).is_ok()
+    });
+    if !registered {
+        return Err(IrqError::TooManyHandlers);
+    }
+    set_masked(line, false);
+    Ok(())
+}
+
+/// Removes `handler` from the handlers of `line`, which is masked if it was the last one
+pub fn unregister_irq(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    let slots = check_line(line)?;
+    let handler = handler as usize;
+    let unregistered = slots.iter().any(|slot| {
+        slot.compare_exchange(handler, 0, Ordering::AcqRel, Ordering::Acquire).is_ok()
+    });
+    if !unregistered {
+        return Err(IrqError::NotRegistered);
+    }
+
+    if !has_handlers(line) {
+        set_masked(line, true);
+    }
+    Ok(())
+}
+
+/// Whether a handler is registered on `line`
+pub fn has_handlers(line: u8) -> bool {
+    HANDLERS.get(line as usize)
+        .map_or(false, |slots| slots.iter().any(|slot| slot.load(Ordering::Acquire) != 0))
+}
+
+
+// The 8259 PICs, see https://wiki.osdev.org/8259_PIC
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+
+/// OCW3 to read the in-service register on the next read of the command port
+const READ_ISR: u8 = 0x0b;
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// The data port holding the interrupt mask register of `line`, and its bit
+fn imr_port(line: u8) -> (Port<u8>, u8) {
+    if line < 8 {
+        (Port::new(MASTER_DATA), 1 << line)
+    } else {
+        (Port::new(SLAVE_DATA), 1 << (line - 8))
+    }
+}
+
+/// Whether `line` is masked in the interrupt mask register of the PICs
+pub fn is_masked_on_pic(line: u8) -> bool {
+    let (port, bit) = imr_port(line);
+    unsafe { port.read() & bit != 0 }
+}
+
+fn set_masked_on_pic(line: u8, masked: bool) {
+    let (port, bit) = imr_port(line);
+    unsafe {
+        let imr = port.read();
+        port.write(if masked { imr | bit } else { imr & !bit });
+    }
+}
+
+/// Masks or unmasks `line` on the controller in use
+fn set_masked(line: u8, masked: bool) {
+    without_interrupts(|| {
+        if !super::set_masked_on_apic(line, masked) {
+            set_masked_on_pic(line, masked);
+        }
+    });
+}
+
+/// Masks the lines nobody handles on the PICs, unmasks the others and the cascade
+pub(super) fn sync_pic_masks() {
+    without_interrupts(|| {
+        for line in 0..IRQ_LINES {
+            set_masked_on_pic(line, line != CASCADE_LINE && !has_handlers(line));
+        }
+    });
+}
+
+/// Whether the interrupt on `line` is a spurious one from the PICs
+///
+/// The PICs raise IRQ 7 (or IRQ 15 for the slave) when an interrupt goes
+/// away before being acknowledged, the in-service register tells them apart
+/// from real ones.
+fn is_spurious(line: u8) -> bool {
+    let command = match line {
+        7 => Port::<u8>::new(MASTER_COMMAND),
+        15 => Port::<u8>::new(SLAVE_COMMAND),
+        _ => return false,
+    };
+    if controller() != Controller::Pic {
+        return false;
+    }
+    unsafe {
+        command.write(READ_ISR);
+        command.read() & (1 << 7) == 0
+    }
+}
+
+
+fn dispatch(line: u8) {
+    if is_spurious(line) {
+        stats::count_spurious();
+        // The master did see an interrupt, from the slave
+        if line == 15 {
+            unsafe { Port::<u8>::new(MASTER_COMMAND).write(END_OF_INTERRUPT) };
+        }
+        return;
+    }
+    stats::count(PIC_1_OFFSET + line);
+
+    for slot in HANDLERS[line as usize].iter() {
+        let handler = slot.load(Ordering::Acquire);
+        if handler != 0 {
+            let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+            handler(line);
+        }
+    }
+    super::end_of_interrupt(line);
+}
+
+/// The IDT stubs of the lines
+macro_rules! irq_stubs {
+    ($($name:ident => $line:expr),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: &mut InterruptStackFrame) {
+                dispatch($line);
+            }
+        )*
+
+        const STUBS: [extern "x86-interrupt" fn(&mut InterruptStackFrame); IRQ_LINES as usize] = [$($name),*];
+    };
+}
+
+irq_stubs! {
+    irq0 => 0, irq1 => 1, irq2 => 2, irq3 => 3,
+    irq4 => 4, irq5 => 5, irq6 => 6, irq7 => 7,
+    irq8 => 8, irq9 => 9, irq10 => 10, irq11 => 11,
+    irq12 => 12, irq13 => 13, irq14 => 14, irq15 => 15,
+}
+
+/// Sets the stubs of the IRQ lines in `idt`
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    for (line, &stub) in STUBS.iter().enumerate() {
+        idt[PIC_1_OFFSET as usize + line].set_handler_fn(stub);
+    }
+}
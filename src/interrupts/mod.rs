@@ -0,0 +1,181 @@
+pub mod exceptions;
+pub mod irq;
+pub mod stats;
+
+pub use irq::{register_irq, unregister_irq, IrqError, IrqHandler};
+pub use stats::{print_stats, stats, InterruptStats};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::apic::{self, ApicConfig, IoApic, LocalApic, Redirection};
+use crate::arch::port::Port;
+use irq::IRQ_LINES;
+
+use x86_64::structures::idt::{InterruptStackFrame, InterruptDescriptorTable};
+use lazy_static::lazy_static;
+use pic8259_simple::ChainedPics;
+use spin;
+
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+
+pub static PICS: spin::Mutex<ChainedPics> =
+    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Whether `enable_apic` replaced the PICs
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The APICs, once `enable_apic` replaced the PICs
+///
+/// Handlers take this lock to signal the end of interrupts, it must only be
+/// taken with the interrupts disabled elsewhere.
+static APICS: spin::Mutex<Option<Apics>> = spin::Mutex::new(None);
+
+struct Apics {
+    local: LocalApic,
+    io: IoApic,
+    config: ApicConfig,
+}
+
+/// What delivers the hardware interrupts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    /// The two 8259 PICs, what `init` sets up
+    Pic,
+    /// The local APIC and the I/O APIC
+    Apic,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+}
+
+impl InterruptIndex {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// The ISA IRQ raising the interrupt
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        exceptions::set_handlers(&mut idt);
+        irq::set_handlers(&mut idt);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
+
+        idt
+    };
+}
+
+
+/// Loads the IDT and registers the timer and keyboard handlers
+pub fn init_idt() {
+    IDT.load();
+    register_irq(InterruptIndex::Timer.irq(), timer_interrupt_handler)
+        .expect("timer IRQ already registered");
+    register_irq(InterruptIndex::Keyboard.irq(), keyboard_interrupt_handler)
+        .expect("keyboard IRQ already registered");
+}
+
+/// Remaps the PICs, only the lines with a handler are unmasked
+pub fn init_pics() {
+    unsafe { PICS.lock().initialize() };
+    irq::sync_pic_masks();
+}
+
+
+/// The controller currently in use
+pub fn controller() -> Controller {
+    if APIC_ENABLED.load(Ordering::Acquire) { Controller::Apic } else { Controller::Pic }
+}
+
+/// Replaces the PICs by the APICs if the CPU has them, returns the controller in use
+///
+/// The PICs are masked and the ISA IRQs are routed through the I/O APIC to
+/// their usual vectors, masked unless a handler is registered on them. Must
+/// be called after `memory::init` since the registers are reached through
+/// the physical memory mapping, and after `acpi::init` for the wiring
+/// described by the MADT to be used.
+pub fn enable_apic() -> Controller {
+    if !apic::is_supported() {
+        return Controller::Pic;
+    }
+    let config = ApicConfig::detect();
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        apic::disable_pics();
+
+        let mut local_apic = unsafe { LocalApic::new(config.local_apic) };
+        local_apic.enable(apic::SPURIOUS_VECTOR);
+
+        let mut io_apic = unsafe { IoApic::new(config.io_apic) };
+        io_apic.mask_all();
+        for line in (0..IRQ_LINES).filter(|&line| line != irq::CASCADE_LINE) {
+            let irq = config.isa_irqs[line as usize];
+            io_apic.set_redirection(irq.gsi - config.io_apic_gsi_base, Redirection {
+                vector: PIC_1_OFFSET + line,
+                destination: local_apic.id(),
+                active_low: irq.active_low,
+                level_triggered: irq.level_triggered,
+                masked: !irq::has_handlers(line),
+            });
+        }
+
+        *APICS.lock() = Some(Apics { local: local_apic, io: io_apic, config });
+        APIC_ENABLED.store(true, Ordering::Release);
+    });
+    Controller::Apic
+}
+
+/// Masks or unmasks `line` on the I/O APIC, returns false in PIC mode
+fn set_masked_on_apic(line: u8, masked: bool) -> bool {
+    match APICS.lock().as_mut() {
+        Some(Apics { io, config, .. }) => {
+            let pin = config.isa_irqs[line as usize].gsi - config.io_apic_gsi_base;
+            let redirection = io.redirection(pin);
+            io.set_redirection(pin, Redirection { masked, ..redirection });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Signals the end of the interrupt of the IRQ `line` to the controller in use
+fn end_of_interrupt(line: u8) {
+    match APICS.lock().as_mut() {
+        Some(apics) => apics.local.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + line) },
+    }
+}
+
+
+fn timer_interrupt_handler(_line: u8) {
+    crate::time::tick();
+    #[cfg(feature="timer_output")]
+    crate::print!(".");
+}
+
+fn keyboard_interrupt_handler(_line: u8) {
+    let port = Port::<u8>::new(0x60);
+    let scancode = unsafe { port.read() };
+
+    crate::task::keyboard::add_scancode(scancode);
+}
+
+/// Raised by the local APIC for an interrupt that went away, no EOI is expected
+extern "x86-interrupt" fn spurious_interrupt_handler(
+    _stack_frame: &mut InterruptStackFrame)
+{
+    stats::count_spurious();
+}
@@ -0,0 +1,96 @@
+//! Interrupt counters
+//!
+//! Every exception handler and the IRQ stubs count the interrupts they
+//! receive, by vector. Spurious interrupts are counted apart: they don't
+//! reach any handler. `stats` takes a snapshot, which displays like Linux's
+//! `/proc/interrupts`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::exceptions::exception_name;
+use super::irq::{self, IRQ_LINES};
+use super::{controller, Controller, PIC_1_OFFSET};
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static COUNTS: [AtomicU64; 256] = [ZERO; 256];
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts an interrupt on `vector`
+pub(super) fn count(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub(super) fn count_spurious() {
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Snapshot of the interrupt counters
+#[derive(Clone, Copy)]
+pub struct InterruptStats {
+    /// Interrupts received, by vector
+    pub counts: [u64; 256],
+    /// Spurious interrupts from the PICs or the local APIC
+    pub spurious: u64,
+}
+
+impl InterruptStats {
+    /// Interrupts received on the IRQ `line`
+    pub fn irq(&self, line: u8) -> u64 {
+        self.counts[(PIC_1_OFFSET + line) as usize]
+    }
+
+    /// Exceptions of any kind
+    pub fn exceptions(&self) -> u64 {
+        self.counts[..32].iter().sum()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum::<u64>() + self.spurious
+    }
+}
+
+impl fmt::Display for InterruptStats {
+    /// A line per vector which fired, or which has a handler for IRQs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chip = match controller() {
+            Controller::Pic => "XT-PIC",
+            Controller::Apic => "IO-APIC",
+        };
+
+        writeln!(f, "{:>4} {:>12}", "", "CPU0")?;
+        for (vector, &count) in self.counts.iter().enumerate() {
+            let vector = vector as u8;
+            let line = vector.wrapping_sub(PIC_1_OFFSET);
+            let is_irq = line < IRQ_LINES;
+            if count == 0 && !(is_irq && irq::has_handlers(line)) {
+                continue;
+            }
+
+            write!(f, "{:>3}: {:>12}  ", vector, count)?;
+            if let Some((mnemonic, name)) = exception_name(vector) {
+                writeln!(f, "{:<8} {}", mnemonic, name)?;
+            } else if is_irq {
+                writeln!(f, "{:<8} IRQ{}", chip, line)?;
+            } else {
+                writeln!(f, "{:<8}", "vector")?;
+            }
+        }
+        writeln!(f, "SPU: {:>12}  spurious interrupts", self.spurious)
+    }
+}
+
+/// Takes a snapshot of the counters
+pub fn stats() -> InterruptStats {
+    let mut counts = [0; 256];
+    for (count, counter) in counts.iter_mut().zip(COUNTS.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+    InterruptStats { counts, spurious: SPURIOUS.load(Ordering::Relaxed) }
+}
+
+/// Prints the counters on the serial console
+pub fn print_stats() {
+    crate::serial_print!("{}", stats());
+}
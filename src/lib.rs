@@ -22,6 +22,8 @@ pub mod interrupts;
 pub mod allocator;
 pub mod task;
 pub mod arch;
+pub mod acpi;
+pub mod time;
 
 extern crate rlibc;
 extern crate alloc;
@@ -29,7 +31,7 @@ extern crate alloc;
 pub fn init(){
     gdt::init();    
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::init_pics();
     x86_64::instructions::interrupts::enable();    
 }
 
@@ -46,22 +46,26 @@ fn mem_init(boot_info: &'static BootInfo){
     
     use x86_64::{structures::paging::{MapperAllSizes, Page}, VirtAddr};
     use rost::memory;
-    use rost::memory::BootInfoFrameAllocator;
+    use rost::memory::BuddyFrameAllocator;
     use rost::allocator;
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
 
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
+    memory::install(mapper, frame_allocator);
+    if let Err(err) = rost::acpi::init() {
+        println!("No ACPI tables: {:?}", err);
+    }
+    println!("Interrupt controller: {:?}", rost::interrupts::enable_apic());
+    memory::protect::protect_kernel();
+    rost::gdt::install_ist_stacks();
 
-
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
-                .expect("heap alloc failed");
+    allocator::init_heap().expect("heap alloc failed");
 }
 
-use rost::arch::pit::*;
 use rost::arch::rtc::{RTC, Register};
 #[no_mangle]
 pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
@@ -88,8 +92,7 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
-    let mut ch1 = unsafe { Channel::new(0) };
-    ch1.set_frequency(OperatingMode::RateGenerator, 20 as f64);
+    rost::time::init(20);
     //rost::hlt_loop();
     
     let mut executor = Executor::new();
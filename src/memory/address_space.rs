@@ -0,0 +1,270 @@
+//! Address spaces, the foundation for isolated processes
+//!
+//! Every `AddressSpace` has its own level 4 table. The L4 entries covering
+//! user space (`USER_SPACE_START..USER_SPACE_END`) belong to it, every other
+//! entry is copied from the kernel's table so the kernel stays mapped
+//! whatever the active address space is.
+//!
+//! Address spaces are cloned copy-on-write, see `memory::cow`.
+
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        page_table::PageTableEntry, FrameDeallocator, Page, PageTable, PageTableFlags as Flags,
+        PageSize, PhysFrame, Size2MiB, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use super::{cow, protect};
+use super::paging::{MapError, PageMapper, Translation, UnmapError};
+use super::{with_kernel_memory, BuddyFrameAllocator, KernelMemory};
+
+/// Start of user space, L4 entry 32
+///
+/// The kernel is in L4 entry 0 and the bootloader puts its stack, the boot
+/// info and the physical memory mapping in the next free entries, 1, 2, ...
+/// (one entry per 512GiB of RAM for the mapping). Entries 1 to 31 are left
+/// to it, the kernel's own regions (heap, stacks, vmalloc) are above entry 128.
+pub const USER_SPACE_START: u64 = 0x0000_1000_0000_0000;
+/// End of user space, L4 entry 128
+pub const USER_SPACE_END: u64 = 0x0000_4000_0000_0000;
+
+const USER_L4_ENTRIES: core::ops::Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+/// Whether the page is in user space
+pub fn is_user_page(page: Page) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&page.start_address().as_u64())
+}
+
+
+pub struct AddressSpace {
+    mapper: PageMapper,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user space
+    pub fn new() -> Result<Self, MapError> {
+        with_kernel_memory(|KernelMemory { mapper: kernel, frame_allocator }| {
+            let frame = frame_allocator.allocate(0).ok_or(MapError::FrameAllocationFailed)?;
+            let mut mapper = unsafe { PageMapper::new(frame, kernel.physical_memory_offset()) };
+            mapper.level_4_table().zero();
+
+            let mut space = Self { mapper };
+            space.sync_kernel_entries(kernel);
+            Ok(space)
+        })
+    }
+
+    /// Copies the kernel's L4 entries, so that kernel mappings created
+    /// under a new L4 entry since the last call become visible
+    fn sync_kernel_entries(&mut self, kernel: &mut PageMapper) {
+        let kernel_table = kernel.level_4_table();
+        assert!(USER_L4_ENTRIES.all(|i| kernel_table[i].is_unused()),
+            "the kernel has mappings in user space");
+
+        let table = self.mapper.level_4_table();
+        for (i, entry) in kernel_table.iter().enumerate() {
+            if !USER_L4_ENTRIES.contains(&i) {
+                table[i] = entry.clone();
+            }
+        }
+    }
+
+    /// Frame of the level 4 table
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.mapper.level_4_frame()
+    }
+
+    /// Whether CR3 currently points to this address space
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame()
+    }
+
+    /// Makes this address space the active one
+    ///
+    /// This function is unsafe because references to the user space of the
+    /// previous address space become dangling.
+    pub unsafe fn switch(&mut self) {
+        with_kernel_memory(|mem| self.sync_kernel_entries(&mut mem.mapper));
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.level_4_frame(), flags);
+    }
+
+    /// Switches back to the kernel's own page table
+    ///
+    /// Unsafe for the same reason as `switch`.
+    pub unsafe fn switch_to_kernel() {
+        let frame = with_kernel_memory(|mem| mem.mapper.level_4_frame());
+        let (_, flags) = Cr3::read();
+        Cr3::write(frame, flags);
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<Translation> {
+        self.mapper.translate(addr)
+    }
+
+    /// Maps zeroed frames to `pages`, which must be in user space
+    ///
+    /// `PRESENT` and `USER_ACCESSIBLE` are added to `flags`, and `NO_EXECUTE`
+    /// if the pages are writable.
+    pub fn map_user(&mut self, pages: impl IntoIterator<Item = Page>, flags: Flags) -> Result<(), MapError> {
+        let flags = protect::wx(flags | Flags::PRESENT | Flags::USER_ACCESSIBLE);
+        let active = self.is_active();
+        let mapper = &mut self.mapper;
+
+        with_kernel_memory(|mem| {
+            for page in pages {
+                assert!(is_user_page(page), "{:?} isn't in user space", page);
+                let frame = mem.frame_allocator.allocate(0).ok_or(MapError::FrameAllocationFailed)?;
+                unsafe {
+                    zero_frame(mapper, frame);
+                    let flush = mapper.map(page, frame, flags, &mut mem.frame_allocator)?;
+                    if active { flush.flush() } else { flush.ignore() }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Unmaps `pages` (in user space) and frees their frames
+    pub fn unmap_user(&mut self, pages: impl IntoIterator<Item = Page>) -> Result<(), UnmapError> {
+        let active = self.is_active();
+        let mapper = &mut self.mapper;
+
+        with_kernel_memory(|mem| {
+            for page in pages {
+                assert!(is_user_page(page), "{:?} isn't in user space", page);
+                let (frame, flush) = mapper.unmap(page, &mut mem.frame_allocator)?;
+                unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                if active { flush.flush() } else { flush.ignore() }
+            }
+            Ok(())
+        })
+    }
+
+    /// Creates a copy of this address space sharing its frames
+    ///
+    /// The writable pages become read-only copy-on-write pages in both
+    /// spaces, they are only duplicated when one of them writes to it.
+    pub fn clone_cow(&mut self) -> Result<AddressSpace, MapError> {
+        let mut child = AddressSpace::new()?;
+        let active = self.is_active();
+        let parent = &self.mapper;
+        let child_mapper = &mut child.mapper;
+
+        with_kernel_memory(|mem| unsafe {
+            let level_4_table = table(parent, parent.level_4_frame());
+            for i in USER_L4_ENTRIES {
+                let addr = (i as u64) << 39;
+                share_subtree(parent, &mut level_4_table[i], 4, addr, child_mapper, &mut mem.frame_allocator)?;
+            }
+            Ok(())
+        })?;
+
+        // Our writable pages are now read-only
+        if active {
+            tlb::flush_all();
+        }
+        Ok(child)
+    }
+
+    /// The page table of this address space
+    pub fn mapper(&mut self) -> &mut PageMapper {
+        &mut self.mapper
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees every frame mapped in user space, the page tables and the L4 table
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { Self::switch_to_kernel() };
+        }
+
+        let mapper = &self.mapper;
+        with_kernel_memory(|mem| unsafe {
+            let level_4_table = table(mapper, mapper.level_4_frame());
+            for i in USER_L4_ENTRIES {
+                free_subtree(mapper, &level_4_table[i], 4, &mut mem.frame_allocator);
+            }
+            mem.frame_allocator.deallocate_frame(mapper.level_4_frame());
+        });
+    }
+}
+
+
+unsafe fn table(mapper: &PageMapper, frame: PhysFrame) -> &'static mut PageTable {
+    &mut *(mapper.physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+unsafe fn zero_frame(mapper: &PageMapper, frame: PhysFrame) {
+    let ptr: *mut u8 = (mapper.physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    core::ptr::write_bytes(ptr, 0, Size4KiB::SIZE as usize);
+}
+
+/// Shares what the entry of a level `level` table of the parent points to
+/// with `child`, recursively, `addr` being the first address it covers
+unsafe fn share_subtree(parent: &PageMapper, entry: &mut PageTableEntry, level: u8, addr: u64,
+    child: &mut PageMapper, frame_allocator: &mut BuddyFrameAllocator) -> Result<(), MapError>
+{
+    if !entry.flags().contains(Flags::PRESENT) {
+        return Ok(());
+    }
+
+    if level == 1 {
+        let flags = cow::shared_flags(entry.flags());
+        let frame = PhysFrame::containing_address(entry.addr());
+        entry.set_flags(flags);
+
+        frame_allocator.share(frame);
+        let page = Page::containing_address(VirtAddr::new(addr));
+        match child.map(page, frame, flags, frame_allocator) {
+            Ok(flush) => flush.ignore(),
+            Err(err) => {
+                frame_allocator.free(frame, 0);
+                return Err(err);
+            }
+        }
+        return Ok(());
+    }
+
+    // `map_user` only maps 4KiB pages
+    assert!(!entry.flags().contains(Flags::HUGE_PAGE), "huge pages can't be shared copy-on-write");
+
+    let frame = PhysFrame::containing_address(entry.addr());
+    let entry_size = 1u64 << (12 + 9 * (level as u64 - 1));
+    for (i, child_entry) in table(parent, frame).iter_mut().enumerate() {
+        share_subtree(parent, child_entry, level - 1, addr + i as u64 * entry_size, child, frame_allocator)?;
+    }
+    Ok(())
+}
+
+/// Frees what the entry of a level `level` table points to, recursively
+unsafe fn free_subtree(mapper: &PageMapper, entry: &PageTableEntry, level: u8,
+    frame_allocator: &mut BuddyFrameAllocator)
+{
+    if !entry.flags().contains(Flags::PRESENT) {
+        return;
+    }
+    let huge = level > 1 && entry.flags().contains(Flags::HUGE_PAGE);
+
+    if level == 1 || huge {
+        // A mapped page. 1GiB pages can't come from the frame allocator,
+        // they can only map memory the address space doesn't own
+        match level {
+            3 => {}
+            2 => frame_allocator.deallocate_frame(PhysFrame::<Size2MiB>::containing_address(entry.addr())),
+            _ => frame_allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(entry.addr())),
+        }
+        return;
+    }
+
+    let frame = PhysFrame::containing_address(entry.addr());
+    for child in table(mapper, frame).iter() {
+        free_subtree(mapper, child, level - 1, frame_allocator);
+    }
+    frame_allocator.deallocate_frame(frame);
+}
@@ -0,0 +1,435 @@
+//! A buddy allocator for physical frames
+//!
+//! Free memory is kept as blocks of 2^order contiguous 4KiB frames, each block
+//! being aligned on its own size. There is one free list per order, from
+//! order 0 (4KiB) up to `MAX_ORDER` (2MiB). Allocating splits a bigger block
+//! in two "buddies" as many times as needed, freeing merges a block back with
+//! its buddy as long as the buddy is free too.
+//!
+//! The bookkeeping is a `FrameInfo` array with one entry per physical frame.
+//! We can't use the heap (it needs frames to exist!) so this array is carved
+//! out of the first usable region that is big enough and accessed through the
+//! physical memory mapping set up by the bootloader.
+//!
+//! Memory is split in zones (`Zone`) for the devices that can't address all
+//! of it, every zone having its own free lists. Zone boundaries are aligned
+//! on the biggest block size so a block never straddles two zones.
+//!
+//! Allocated blocks are reference counted so that frames can be shared, e.g.
+//! by copy-on-write mappings: `share` adds a reference and `free` only
+//! releases the block when its last reference is dropped.
+//!
+//! See https://wiki.osdev.org/Page_Frame_Allocation
+
+use core::mem::size_of;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Size of an order 0 block
+pub const FRAME_SIZE: u64 = Size4KiB::SIZE;
+
+/// Biggest order handed out by the allocator, 2^9 * 4KiB = 2MiB
+pub const MAX_ORDER: usize = 9;
+
+const ORDER_COUNT: usize = MAX_ORDER + 1;
+
+/// Marks the end of a free list
+const NIL: u32 = u32::MAX;
+
+const ZONE_COUNT: usize = 3;
+
+/// Physical memory zones, by the addresses devices can reach
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Below 16MiB, for legacy ISA DMA
+    Dma,
+    /// Below 4GiB, for 32-bit devices
+    Dma32,
+    /// Everything else
+    Normal,
+}
+
+impl Zone {
+    pub const ALL: [Zone; ZONE_COUNT] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    /// First address above the zone
+    pub fn limit(self) -> u64 {
+        match self {
+            Zone::Dma => 16 << 20,
+            Zone::Dma32 => 4 << 30,
+            Zone::Normal => u64::MAX,
+        }
+    }
+
+    /// Zone of a physical address
+    pub fn of(addr: PhysAddr) -> Zone {
+        Zone::ALL.iter().copied().find(|z| addr.as_u64() < z.limit()).unwrap()
+    }
+
+    fn of_index(index: u32) -> Zone {
+        Zone::of(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum FrameState {
+    /// Not usable memory, or the frames holding the `FrameInfo` array
+    Unmanaged,
+    /// First frame of a free block, the block is linked in a free list
+    Free,
+    /// First frame of an allocated block, or any frame inside a block
+    Used,
+}
+
+/// Per-frame bookkeeping
+///
+/// `next` and `prev` link the free lists together and are only meaningful
+/// when the frame is the head of a free block, `refs` only when it is the
+/// head of an allocated block.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct FrameInfo {
+    next: u32,
+    prev: u32,
+    order: u8,
+    state: FrameState,
+    refs: u16,
+}
+
+impl FrameInfo {
+    const UNMANAGED: Self = Self { next: NIL, prev: NIL, order: 0, state: FrameState::Unmanaged, refs: 0 };
+}
+
+/// Physical memory manager built from the bootloader's memory map.
+pub struct BuddyFrameAllocator {
+    frames: &'static mut [FrameInfo],
+    /// Free lists of every zone, by order
+    free_lists: [[u32; ORDER_COUNT]; ZONE_COUNT],
+    total_frames: usize,
+    free_frames: [usize; ZONE_COUNT],
+}
+
+impl BuddyFrameAllocator {
+    /// Create a frame allocator managing every `Usable` region of the memory map.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid, that all frames that are marked as `USABLE` in it are
+    /// really unused and that the complete physical memory is mapped at
+    /// `physical_memory_offset`. It must also only be called once.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable = || memory_map.iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr()..r.range.end_addr());
+
+        // One entry for every frame up to the end of the last usable region
+        let frame_count = usable().map(|r| r.end).max().unwrap_or(0) / FRAME_SIZE;
+        let table_size = align_up(frame_count * size_of::<FrameInfo>() as u64, FRAME_SIZE);
+
+        // Steal the beginning of the first region big enough for the table
+        let table_start = usable()
+            .find(|r| r.end - r.start >= table_size)
+            .expect("no usable region is big enough for the frame table")
+            .start;
+
+        let ptr: *mut FrameInfo = (physical_memory_offset + table_start).as_mut_ptr();
+        let frames = core::slice::from_raw_parts_mut(ptr, frame_count as usize);
+        for info in frames.iter_mut() {
+            *info = FrameInfo::UNMANAGED;
+        }
+
+        let mut allocator = Self {
+            frames,
+            free_lists: [[NIL; ORDER_COUNT]; ZONE_COUNT],
+            total_frames: 0,
+            free_frames: [0; ZONE_COUNT],
+        };
+
+        for range in usable() {
+            let mut start = range.start;
+            if start == table_start { start += table_size; }
+            if start >= range.end { continue; }
+
+            let (first, last) = (start / FRAME_SIZE, range.end / FRAME_SIZE);
+            allocator.total_frames += (last - first) as usize;
+            allocator.release_range(first as usize, last as usize);
+        }
+
+        allocator
+    }
+
+    /// Number of usable frames managed by the allocator
+    pub fn total_frames(&self) -> usize { self.total_frames }
+
+    /// Number of frames currently free
+    pub fn free_frames(&self) -> usize { self.free_frames.iter().sum() }
+
+    /// Number of frames of `zone` currently free
+    pub fn free_frames_in(&self, zone: Zone) -> usize { self.free_frames[zone as usize] }
+
+    /// Number of frames currently handed out
+    pub fn used_frames(&self) -> usize { self.total_frames - self.free_frames() }
+
+    /// Number of free blocks of the given order, in every zone
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        for lists in self.free_lists.iter() {
+            let mut index = lists[order];
+            while index != NIL {
+                count += 1;
+                index = self.frames[index as usize].next;
+            }
+        }
+        count
+    }
+
+    /// Allocates a block of 2^`order` contiguous frames aligned on its size.
+    ///
+    /// The highest zones are used first, to keep the low memory for the
+    /// devices which need it.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        self.allocate_in(Zone::Normal, order)
+    }
+
+    /// Allocates a block like `allocate`, but entirely below `zone.limit()`
+    pub fn allocate_in(&mut self, zone: Zone, order: usize) -> Option<PhysFrame> {
+        assert!(order <= MAX_ORDER, "order {} is too big", order);
+
+        let zone = Zone::ALL[..=zone as usize].iter().rev()
+            .find(|&&z| (order..ORDER_COUNT).any(|o| self.free_lists[z as usize][o] != NIL))?;
+        let lists = &self.free_lists[*zone as usize];
+        let mut current = (order..ORDER_COUNT).find(|&o| lists[o] != NIL).unwrap();
+        let index = lists[current];
+        self.unlink(index, current);
+
+        // Split the block until it has the right size, giving back the upper halves
+        while current > order {
+            current -= 1;
+            self.push(index + (1 << current), current);
+        }
+
+        self.mark_used(index, order);
+        Some(frame_from_index(index))
+    }
+
+    /// Allocates a single frame below `zone.limit()`
+    pub fn allocate_frame_in(&mut self, zone: Zone) -> Option<PhysFrame> {
+        self.allocate_in(zone, 0)
+    }
+
+    /// Allocates `count` physically contiguous frames starting on an `align`
+    /// bytes boundary (a power of two), entirely below `zone.limit()`
+    ///
+    /// Unlike `allocate` the count isn't limited to a power of two nor to
+    /// `MAX_ORDER`. The frames must be given back with `free_contiguous`.
+    pub fn allocate_contiguous(&mut self, count: usize, align: u64, zone: Zone) -> Option<PhysFrame> {
+        assert!(count > 0 && align.is_power_of_two(), "invalid contiguous allocation");
+        let align = (align / FRAME_SIZE).max(1) as usize;
+        let size = count.max(align).next_power_of_two();
+
+        // Small enough for a single block: its alignment is its size
+        if size <= 1 << MAX_ORDER {
+            let order = size.trailing_zeros() as usize;
+            let frame = self.allocate_in(zone, order)?;
+            let index = index_of(frame) as usize;
+            self.release_range(index + count, index + size);
+            return Some(frame);
+        }
+
+        // Otherwise look for a run of free blocks of the biggest order
+        let blocks = (count + (1 << MAX_ORDER) - 1) >> MAX_ORDER;
+        for z in Zone::ALL[..=zone as usize].iter().rev() {
+            let mut head = self.free_lists[*z as usize][MAX_ORDER];
+            while head != NIL {
+                let next = self.frames[head as usize].next;
+                if head as usize % align == 0 && self.is_free_run(head, blocks, zone) {
+                    for i in 0..blocks {
+                        let block = head + (i << MAX_ORDER) as u32;
+                        self.unlink(block, MAX_ORDER);
+                        self.mark_used(block, MAX_ORDER);
+                        if i > 0 { self.frames[block as usize].refs = 0; }
+                    }
+
+                    let index = head as usize;
+                    self.release_range(index + count, index + (blocks << MAX_ORDER));
+                    return Some(frame_from_index(head));
+                }
+                head = next;
+            }
+        }
+        None
+    }
+
+    /// Gives back frames allocated by `allocate_contiguous` with the same `count`.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// frames are not used anymore.
+    pub unsafe fn free_contiguous(&mut self, frame: PhysFrame, count: usize) {
+        let index = index_of(frame) as usize;
+        let info = self.frames[index];
+        assert!(info.state == FrameState::Used && info.refs == 1,
+            "freeing {:?} but it isn't an allocated block", frame);
+
+        self.release_range(index, index + count);
+    }
+
+    /// Whether the `blocks` blocks of order `MAX_ORDER` starting at `head` are
+    /// free, and below the limit of `zone`
+    fn is_free_run(&self, head: u32, blocks: usize, zone: Zone) -> bool {
+        let end = (head as u64 + (blocks << MAX_ORDER) as u64) * FRAME_SIZE;
+        end <= zone.limit() && (0..blocks).all(|i| {
+            match self.frames.get(head as usize + (i << MAX_ORDER)) {
+                Some(info) => info.state == FrameState::Free && info.order as usize == MAX_ORDER,
+                None => false,
+            }
+        })
+    }
+
+    /// Marks the block at `index` as allocated, with a single reference
+    fn mark_used(&mut self, index: u32, order: usize) {
+        let info = &mut self.frames[index as usize];
+        info.state = FrameState::Used;
+        info.order = order as u8;
+        info.refs = 1;
+        self.free_frames[Zone::of_index(index) as usize] -= 1 << order;
+    }
+
+    /// Drops a reference to a block previously returned by `allocate` with
+    /// the same `order`, the block is given back when it was the last one.
+    ///
+    /// This function is unsafe because the caller must guarantee that the block
+    /// is not used anymore (through this reference).
+    pub unsafe fn free(&mut self, frame: PhysFrame, order: usize) {
+        let index = index_of(frame);
+        let info = &mut self.frames[index as usize];
+        assert!(info.state == FrameState::Used && info.order as usize == order && info.refs > 0,
+            "freeing {:?} with order {} but it isn't an allocated block of that order", frame, order);
+
+        info.refs -= 1;
+        if info.refs == 0 {
+            self.release(index, order);
+        }
+    }
+
+    /// Adds a reference to an allocated block, it then takes one more `free` to release it
+    pub fn share(&mut self, frame: PhysFrame) {
+        let info = &mut self.frames[index_of(frame) as usize];
+        assert!(info.state == FrameState::Used && info.refs > 0,
+            "sharing {:?} but it isn't an allocated block", frame);
+        info.refs = info.refs.checked_add(1).expect("too many references to a frame");
+    }
+
+    /// Number of references to the allocated block starting at `frame`, 0 if
+    /// it isn't the start of an allocated block
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        match self.frames.get(index_of(frame) as usize) {
+            Some(info) if info.state == FrameState::Used => info.refs as usize,
+            _ => 0,
+        }
+    }
+
+    /// Releases the frames `first..last` by splitting them in the biggest aligned blocks possible
+    fn release_range(&mut self, mut first: usize, last: usize) {
+        while first < last {
+            let order = (0..ORDER_COUNT).rev()
+                .find(|&o| first % (1 << o) == 0 && first + (1 << o) <= last)
+                .unwrap();
+            self.release(first as u32, order);
+            first += 1 << order;
+        }
+    }
+
+    /// Puts a block back in the free lists, merging it with its buddies
+    fn release(&mut self, mut index: u32, mut order: usize) {
+        self.free_frames[Zone::of_index(index) as usize] += 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            match self.frames.get(buddy as usize) {
+                Some(info) if info.state == FrameState::Free && info.order as usize == order => {}
+                _ => break,
+            }
+            self.unlink(buddy, order);
+            self.frames[buddy as usize].state = FrameState::Used;
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push(index, order);
+    }
+
+    /// Adds a free block at the front of its free list
+    fn push(&mut self, index: u32, order: usize) {
+        let zone = Zone::of_index(index) as usize;
+        let head = self.free_lists[zone][order];
+        if head != NIL {
+            self.frames[head as usize].prev = index;
+        }
+        self.frames[index as usize] = FrameInfo {
+            next: head,
+            prev: NIL,
+            order: order as u8,
+            state: FrameState::Free,
+            refs: 0,
+        };
+        self.free_lists[zone][order] = index;
+    }
+
+    /// Removes a free block from its free list
+    fn unlink(&mut self, index: u32, order: usize) {
+        let FrameInfo { next, prev, .. } = self.frames[index as usize];
+        if prev == NIL {
+            self.free_lists[Zone::of_index(index) as usize][order] = next;
+        } else {
+            self.frames[prev as usize].next = next;
+        }
+        if next != NIL {
+            self.frames[next as usize].prev = prev;
+        }
+    }
+}
+
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
+
+fn index_of<S: PageSize>(frame: PhysFrame<S>) -> u32 {
+    (frame.start_address().as_u64() / FRAME_SIZE) as u32
+}
+
+fn frame_from_index(index: u32) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate(MAX_ORDER)?;
+        Some(PhysFrame::containing_address(frame.start_address()))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free(frame, 0);
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.free(PhysFrame::containing_address(frame.start_address()), MAX_ORDER);
+    }
+}
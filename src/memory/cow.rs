@@ -0,0 +1,86 @@
+//! Copy-on-write
+//!
+//! `AddressSpace::clone_cow` shares the frames of an address space with its
+//! copy instead of duplicating them: writable pages are mapped read-only in
+//! both spaces and marked with `COW`. The first write to such a page faults,
+//! `handle_page_fault` then gives the faulting space its own copy of the
+//! frame, or simply makes the page writable again if nobody else references
+//! the frame anymore.
+//!
+//! The kernel must fault on writes to read-only pages too, `init` sets
+//! CR0.WP for that.
+
+use x86_64::{
+    registers::control::{Cr0, Cr0Flags, Cr3},
+    structures::idt::PageFaultErrorCode,
+    structures::paging::{Page, PageSize, PageTableFlags as Flags, PhysFrame, Size4KiB},
+    VirtAddr,
+};
+
+use super::paging::PageMapper;
+use super::{physical_memory_offset, try_with_kernel_memory, KernelMemory};
+
+/// Marks the pages that are read-only because their frame is shared, an
+/// available bit of the page table entries
+pub const COW: Flags = Flags::BIT_9;
+
+/// Makes writes to read-only pages fault in kernel mode too
+pub fn init() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+}
+
+/// The flags of a page shared copy-on-write, given its flags before
+pub fn shared_flags(flags: Flags) -> Flags {
+    if flags.contains(Flags::WRITABLE) {
+        (flags - Flags::WRITABLE) | COW
+    } else {
+        flags
+    }
+}
+
+/// Tries to resolve a write fault on a copy-on-write page of the active
+/// address space, returns whether the faulting instruction can be restarted
+///
+/// Like `demand::handle_page_fault`, it never waits for the kernel memory lock.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let write_to_present = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if !error_code.contains(write_to_present) {
+        return false;
+    }
+
+    // Every address space is edited with the kernel memory lock held, so
+    // holding it is enough to edit the active one
+    try_with_kernel_memory(|KernelMemory { frame_allocator, .. }| {
+        let mut mapper = unsafe { PageMapper::new(Cr3::read().0, physical_memory_offset()) };
+        let translation = match mapper.translate(addr) {
+            Some(t) if t.flags.contains(COW) && t.page_size == Size4KiB::SIZE => t,
+            _ => return false,
+        };
+
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let old_frame = PhysFrame::containing_address(translation.frame_start);
+        let flags = (translation.flags - COW) | Flags::WRITABLE;
+
+        if frame_allocator.ref_count(old_frame) == 1 {
+            // The other spaces are gone, the frame is ours
+            mapper.update_flags(page, flags).expect("COW page vanished").flush();
+            return true;
+        }
+
+        let frame = match frame_allocator.allocate(0) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        unsafe {
+            let offset = physical_memory_offset();
+            let src: *const u8 = (offset + old_frame.start_address().as_u64()).as_ptr();
+            let dst: *mut u8 = (offset + frame.start_address().as_u64()).as_mut_ptr();
+            core::ptr::copy_nonoverlapping(src, dst, Size4KiB::SIZE as usize);
+
+            let (old_frame, flush) = mapper.remap(page, frame, flags).expect("COW page vanished");
+            flush.flush();
+            frame_allocator.free(old_frame, 0);
+        }
+        true
+    }).unwrap_or(false)
+}
@@ -0,0 +1,233 @@
+//! Demand paging
+//!
+//! A region registered here reserves a range of the kernel address space
+//! without mapping anything. The first access to one of its pages page
+//! faults, `handle_page_fault` then allocates a frame, lets the region's
+//! `Backing` fill it, maps it and the faulting instruction is restarted.
+//!
+//! The registry is a fixed size table so that neither registering nor
+//! resolving a fault touches the heap.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    structures::idt::PageFaultErrorCode,
+    structures::paging::{FrameDeallocator, Page, PageSize, PageTableFlags as Flags, Size4KiB},
+    VirtAddr,
+};
+
+use super::paging::MapError;
+use super::protect;
+use super::{try_with_kernel_memory, with_kernel_memory, KernelMemory};
+
+/// Maximum number of regions registered at the same time
+pub const MAX_REGIONS: usize = 32;
+
+/// What provides the content of the pages of a region
+pub trait Backing: Sync {
+    /// Fills `page` (a newly allocated frame), which is at `offset` bytes
+    /// from the start of the region
+    fn fill(&self, offset: u64, page: &mut [u8]);
+}
+
+/// Zero filled memory
+pub struct Anonymous;
+
+impl Backing for Anonymous {
+    fn fill(&self, _offset: u64, page: &mut [u8]) {
+        for byte in page.iter_mut() {
+            *byte = 0;
+        }
+    }
+}
+
+
+#[derive(Clone, Copy)]
+pub struct Region {
+    start: VirtAddr,
+    size: u64,
+    /// Flags of the pages mapped in the region, `PRESENT` is implied and
+    /// writable regions are NX
+    flags: Flags,
+    backing: &'static dyn Backing,
+}
+
+impl Region {
+    /// A region of `size` bytes at `start`, both must be page aligned
+    pub fn new(start: VirtAddr, size: u64, flags: Flags, backing: &'static dyn Backing) -> Self {
+        Region { start, size, flags: protect::wx(flags | Flags::PRESENT), backing }
+    }
+
+    /// Zero filled region
+    pub fn anonymous(start: VirtAddr, size: u64, flags: Flags) -> Self {
+        Region::new(start, size, flags, &Anonymous)
+    }
+
+    pub fn start(&self) -> VirtAddr { self.start }
+    pub fn size(&self) -> u64 { self.size }
+    pub fn flags(&self) -> Flags { self.flags }
+
+    fn end(&self) -> u64 {
+        self.start.as_u64() + self.size
+    }
+
+    fn contains(&self, addr: VirtAddr) -> bool {
+        (self.start.as_u64()..self.end()).contains(&addr.as_u64())
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(self.start);
+        Page::range(start, start + self.size / Size4KiB::SIZE)
+    }
+}
+
+impl core::fmt::Debug for Region {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Region")
+            .field("start", &self.start)
+            .field("size", &self.size)
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The start or the size isn't page aligned, or the size is 0
+    Unaligned,
+    /// The region overlaps a registered one
+    Overlapping,
+    /// `MAX_REGIONS` regions are registered already
+    TooManyRegions,
+}
+
+
+static REGIONS: Mutex<[Option<Region>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+/// Number of faults resolved by mapping a page
+static RESOLVED_FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers `region`, its pages are mapped as they are accessed
+///
+/// The range must not be mapped already: faults are only taken for
+/// unmapped pages.
+pub fn register(region: Region) -> Result<(), RegionError> {
+    let aligned = |x: u64| x % Size4KiB::SIZE == 0;
+    if !aligned(region.start.as_u64()) || !aligned(region.size) || region.size == 0 {
+        return Err(RegionError::Unaligned);
+    }
+
+    let mut regions = REGIONS.lock();
+    let overlaps = regions.iter().flatten()
+        .any(|r| region.start.as_u64() < r.end() && r.start.as_u64() < region.end());
+    if overlaps {
+        return Err(RegionError::Overlapping);
+    }
+
+    let slot = regions.iter_mut().find(|r| r.is_none()).ok_or(RegionError::TooManyRegions)?;
+    *slot = Some(region);
+    Ok(())
+}
+
+/// Removes the region starting at `start`, unmapping the pages that were
+/// accessed and freeing their frames
+///
+/// This function is unsafe because the caller must guarantee that nothing
+/// references the memory of the region anymore.
+pub unsafe fn unregister(start: VirtAddr) -> Option<Region> {
+    let mut regions = REGIONS.lock();
+    let region = regions.iter_mut()
+        .find(|r| matches!(r, Some(r) if r.start == start))?
+        .take()?;
+
+    with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        for page in region.pages() {
+            if let Ok((frame, flush)) = mapper.unmap(page, frame_allocator) {
+                frame_allocator.deallocate_frame(frame);
+                flush.flush();
+            }
+        }
+    });
+    Some(region)
+}
+
+/// The region containing `addr`, if any
+pub fn region_at(addr: VirtAddr) -> Option<Region> {
+    REGIONS.lock().iter().flatten().find(|r| r.contains(addr)).copied()
+}
+
+/// Number of page faults resolved since boot
+pub fn resolved_faults() -> usize {
+    RESOLVED_FAULTS.load(Ordering::Relaxed)
+}
+
+
+/// Tries to resolve a page fault at `addr`, returns whether the faulting
+/// instruction can be restarted
+///
+/// Called by the page fault handler. It never waits for a lock: a fault
+/// taken while the regions or the kernel memory are locked (e.g. by the
+/// heap growing) isn't resolved.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    // The page is present, the access itself isn't allowed
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    let region = match REGIONS.try_lock() {
+        Some(regions) => match regions.iter().flatten().find(|r| r.contains(addr)) {
+            Some(region) => *region,
+            None => return false,
+        },
+        None => return false,
+    };
+
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !region.flags.contains(Flags::WRITABLE)
+    {
+        return false;
+    }
+    if error_code.contains(PageFaultErrorCode::USER_MODE)
+        && !region.flags.contains(Flags::USER_ACCESSIBLE)
+    {
+        return false;
+    }
+
+    let page = Page::containing_address(addr);
+    let offset = page.start_address() - region.start;
+
+    let resolved = try_with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let frame = match frame_allocator.allocate(0) {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        // Fill the frame through the physical memory mapping, before it is mapped
+        let ptr: *mut u8 = (mapper.physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+        let content = unsafe { core::slice::from_raw_parts_mut(ptr, Size4KiB::SIZE as usize) };
+        region.backing.fill(offset, content);
+
+        let mapped = match unsafe { mapper.map(page, frame, region.flags, frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(err) => {
+                unsafe { frame_allocator.free(frame, 0) };
+                // Mapped in the meantime, e.g. by `map_range`: retrying is fine
+                matches!(err, MapError::PageAlreadyMapped(_))
+            }
+        };
+
+        // The kernel tables aren't necessarily the active ones: an address
+        // space only sees the kernel L4 entries which existed when it was
+        // created, retrying would fault again
+        mapped && unsafe { super::translate_addr(addr, mapper.physical_memory_offset()) }.is_some()
+    });
+
+    let resolved = resolved.unwrap_or(false);
+    if resolved {
+        RESOLVED_FAULTS.fetch_add(1, Ordering::Relaxed);
+    }
+    resolved
+}
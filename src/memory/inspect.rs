@@ -0,0 +1,266 @@
+//! Inspection of the page tables and of the bootloader memory map
+//!
+//! `for_each_range` walks a page table hierarchy and reports what is mapped
+//! as ranges of virtual memory, merging the pages that follow each other in
+//! both virtual and physical memory with the same access rights. The memory
+//! map regions are merged the same way by `memory_regions`.
+//!
+//! Nothing here allocates, the `print_*` functions write to the serial port.
+
+use core::fmt;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{PageTable, PageTableFlags as Flags, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+use super::paging::PageMapper;
+use crate::serial_println;
+
+/// Effective access rights of a mapping, all the levels taken into account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    /// Writable at every level
+    pub writable: bool,
+    /// Accessible from user mode at every level
+    pub user: bool,
+    /// No-execute at any level
+    pub no_execute: bool,
+    /// Mapped by 2MiB or 1GiB pages
+    pub huge: bool,
+    pub global: bool,
+}
+
+impl Access {
+    /// Rights of the whole address space, before any table restricts them
+    const ALL: Access = Access { writable: true, user: true, no_execute: false, huge: false, global: false };
+
+    /// Rights once restricted by an entry of a table of the given level
+    fn restrict(self, flags: Flags, level: u8) -> Access {
+        let leaf = level == 1 || flags.contains(Flags::HUGE_PAGE);
+        Access {
+            writable: self.writable && flags.contains(Flags::WRITABLE),
+            user: self.user && flags.contains(Flags::USER_ACCESSIBLE),
+            no_execute: self.no_execute || flags.contains(Flags::NO_EXECUTE),
+            huge: leaf && level > 1,
+            global: leaf && flags.contains(Flags::GLOBAL),
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    /// Like `W-NX--`: writable, user, no-execute, huge and global
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}{}{}",
+            if self.writable { "W" } else { "-" },
+            if self.user { "U" } else { "-" },
+            if self.no_execute { "NX" } else { "--" },
+            if self.huge { "H" } else { "-" },
+            if self.global { "G" } else { "-" })
+    }
+}
+
+
+/// Virtual memory mapped to contiguous physical memory with the same rights
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    /// In bytes
+    pub size: u64,
+    pub access: Access,
+}
+
+impl MappedRange {
+    pub fn virt_end(&self) -> VirtAddr {
+        self.virt + self.size
+    }
+
+    pub fn phys_end(&self) -> PhysAddr {
+        self.phys + self.size
+    }
+
+    /// Whether `next` directly follows this range and can be merged with it
+    fn continued_by(&self, next: &MappedRange) -> bool {
+        self.virt.as_u64() + self.size == next.virt.as_u64()
+            && self.phys_end() == next.phys
+            && self.access == next.access
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}-{:#018x} -> {:#012x}-{:#012x} {} {:>10}K",
+            self.virt.as_u64(), self.virt_end().as_u64(),
+            self.phys.as_u64(), self.phys_end().as_u64(),
+            self.access, self.size / 1024)
+    }
+}
+
+/// Size of the memory covered by one entry of a table of the given level
+fn entry_size(level: u8) -> u64 {
+    1 << (12 + 9 * (level as u64 - 1))
+}
+
+/// Sign extends bit 47, as x86_64 wants it
+fn canonical(addr: u64) -> VirtAddr {
+    VirtAddr::new(((addr << 16) as i64 >> 16) as u64)
+}
+
+struct Walker<'a, F: FnMut(&MappedRange)> {
+    physical_memory_offset: VirtAddr,
+    current: Option<MappedRange>,
+    f: &'a mut F,
+}
+
+impl<'a, F: FnMut(&MappedRange)> Walker<'a, F> {
+    fn table(&self, frame: PhysFrame) -> &'static PageTable {
+        let virt = self.physical_memory_offset + frame.start_address().as_u64();
+        unsafe { &*virt.as_ptr() }
+    }
+
+    fn walk(&mut self, table: &PageTable, level: u8, base: u64, access: Access) {
+        for (i, entry) in table.iter().enumerate() {
+            let flags = entry.flags();
+            if !flags.contains(Flags::PRESENT) {
+                continue;
+            }
+            let addr = base + i as u64 * entry_size(level);
+            let access = access.restrict(flags, level);
+
+            if level == 1 || flags.contains(Flags::HUGE_PAGE) {
+                self.add(MappedRange {
+                    virt: canonical(addr),
+                    phys: entry.addr(),
+                    size: entry_size(level),
+                    access,
+                });
+            } else {
+                let next = self.table(PhysFrame::containing_address(entry.addr()));
+                self.walk(next, level - 1, addr, access);
+            }
+        }
+    }
+
+    fn add(&mut self, range: MappedRange) {
+        match &mut self.current {
+            Some(current) if current.continued_by(&range) => current.size += range.size,
+            current => {
+                if let Some(done) = current.replace(range) {
+                    (self.f)(&done);
+                }
+            }
+        }
+    }
+}
+
+/// Calls `f` on every range mapped by `mapper`, in increasing virtual addresses
+pub fn for_each_range(mapper: &PageMapper, mut f: impl FnMut(&MappedRange)) {
+    let mut walker = Walker {
+        physical_memory_offset: mapper.physical_memory_offset(),
+        current: None,
+        f: &mut f,
+    };
+    let level_4_table = walker.table(mapper.level_4_frame());
+    walker.walk(level_4_table, 4, 0, Access::ALL);
+    if let Some(last) = walker.current.take() {
+        (walker.f)(&last);
+    }
+}
+
+/// Prints the ranges mapped by `mapper` on the serial port
+pub fn print_page_tables(mapper: &PageMapper) {
+    serial_println!("virtual                               -> physical                    rights       size");
+    for_each_range(mapper, |range| serial_println!("{}", range));
+}
+
+
+/// A region of the memory map, adjacent regions of the same type merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    pub region_type: MemoryRegionType,
+}
+
+impl Region {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// The regions of the memory map, sorted
+pub fn memory_regions(memory_map: &MemoryMap) -> impl Iterator<Item = Region> + '_ {
+    let mut regions = memory_map.iter().map(|r| Region {
+        start: PhysAddr::new(r.range.start_addr()),
+        end: PhysAddr::new(r.range.end_addr()),
+        region_type: r.region_type,
+    }).peekable();
+
+    // The bootloader gives them sorted, only merging is needed
+    core::iter::from_fn(move || {
+        let mut region = regions.next()?;
+        while let Some(next) = regions.peek() {
+            if next.start != region.end || next.region_type != region.region_type {
+                break;
+            }
+            region.end = next.end;
+            regions.next();
+        }
+        Some(region)
+    })
+}
+
+/// How the physical memory is used, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryTotals {
+    /// Free for the frame allocator
+    pub usable: u64,
+    /// Used by the kernel, its stack, the page tables, the bootloader...
+    pub in_use: u64,
+    /// Reserved by the firmware or by the hardware, ACPI, bad memory
+    pub reserved: u64,
+}
+
+impl MemoryTotals {
+    pub fn total(&self) -> u64 {
+        self.usable + self.in_use + self.reserved
+    }
+}
+
+impl fmt::Display for MemoryTotals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}K usable, {}K in use, {}K reserved, {}K total",
+            self.usable / 1024, self.in_use / 1024, self.reserved / 1024, self.total() / 1024)
+    }
+}
+
+pub fn memory_totals(memory_map: &MemoryMap) -> MemoryTotals {
+    let mut totals = MemoryTotals::default();
+    for region in memory_regions(memory_map) {
+        let size = region.size();
+        match region.region_type {
+            MemoryRegionType::Usable => totals.usable += size,
+            MemoryRegionType::InUse
+            | MemoryRegionType::Kernel
+            | MemoryRegionType::KernelStack
+            | MemoryRegionType::PageTable
+            | MemoryRegionType::Bootloader
+            | MemoryRegionType::FrameZero
+            | MemoryRegionType::BootInfo
+            | MemoryRegionType::Package => totals.in_use += size,
+            _ => totals.reserved += size,
+        }
+    }
+    totals
+}
+
+/// Prints the memory map and its totals on the serial port
+pub fn print_memory_map(memory_map: &MemoryMap) {
+    for region in memory_regions(memory_map) {
+        serial_println!("{:#012x}-{:#012x} {:>10}K {:?}",
+            region.start.as_u64(), region.end.as_u64(), region.size() / 1024, region.region_type);
+    }
+    serial_println!("{}", memory_totals(memory_map));
+}
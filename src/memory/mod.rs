@@ -0,0 +1,262 @@
+
+pub mod buddy;
+pub mod paging;
+pub mod address_space;
+pub mod stack;
+pub mod demand;
+pub mod cow;
+pub mod protect;
+pub mod vmalloc;
+pub mod inspect;
+
+pub use buddy::BuddyFrameAllocator;
+pub use address_space::AddressSpace;
+pub use paging::{MapError, PageMapper};
+pub use stack::KernelStack;
+pub use vmalloc::{ioremap, vmalloc, VmArea};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use x86_64::{
+    structures::paging::{
+        Page, PhysFrame, PageSize, PageTableFlags,
+        Size4KiB, Size2MiB, Size1GiB, FrameAllocator,
+        PageTable},
+    VirtAddr,
+    PhysAddr
+};
+
+
+/// The kernel page table and physical frame allocator
+///
+/// Anything that needs to map memory after boot (e.g. the heap when it grows)
+/// goes through `with_kernel_memory`. Beware: the heap takes this lock to grow,
+/// so nothing must allocate on the heap while holding it.
+pub struct KernelMemory {
+    pub mapper: PageMapper,
+    pub frame_allocator: BuddyFrameAllocator,
+}
+
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+/// Hands the kernel page table and frame allocator over to the memory module.
+pub fn install(mapper: PageMapper, frame_allocator: BuddyFrameAllocator) {
+    let mut memory = KERNEL_MEMORY.lock();
+    assert!(memory.is_none(), "kernel memory installed twice");
+    *memory = Some(KernelMemory { mapper, frame_allocator });
+}
+
+/// Runs `f` with exclusive access to the kernel page table and frame allocator.
+///
+/// Panics if `install` wasn't called before.
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> R {
+    let mut memory = KERNEL_MEMORY.lock();
+    f(memory.as_mut().expect("kernel memory not installed"))
+}
+
+/// Like `with_kernel_memory`, but gives up if the lock is taken (or if the
+/// memory isn't installed yet) instead of spinning
+///
+/// Exception handlers must use this: the code they interrupted may hold the lock.
+pub fn try_with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> Option<R> {
+    let mut memory = KERNEL_MEMORY.try_lock()?;
+    memory.as_mut().map(f)
+}
+
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Where the physical memory is mapped, as given to `init`
+///
+/// For the code that can't wait for the kernel memory lock (e.g. exception handlers).
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+
+/// Initialize a new PageMapper for the active page table.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`. Also, this function must be only called once
+/// to avoid aliasing `&mut` references (which is undefined behavior).
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> PageMapper {
+    use x86_64::registers::control::Cr3;
+
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    // Copy-on-write relies on the kernel faulting on read-only pages
+    cow::init();
+    protect::enable_nx();
+
+    let (level_4_table_frame, _) = Cr3::read();
+    PageMapper::new(level_4_table_frame, physical_memory_offset)
+}
+
+
+/// Returns a mutable reference to the active level 4 table.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`. Also, this function must be only called once
+/// to avoid aliasing `&mut` references (which is undefined behavior).
+pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
+    -> &'static mut PageTable
+{
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr // unsafe
+}
+
+
+/// Use a PageMapper for address translating
+/// 
+/// Translates the given virtual address to the mapped physical address, or
+/// `None` if the address is not mapped.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr)
+    -> Option<PhysAddr>
+{
+    translate_addr_inner(addr, physical_memory_offset)
+}
+
+
+/// Private function that is called by `translate_addr`.
+///
+/// This function is safe to limit the scope of `unsafe` because Rust treats
+/// the whole body of unsafe functions as an unsafe block. This function must
+/// only be reachable through `unsafe fn` from outside of this module.
+fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
+    -> Option<PhysAddr>
+{
+    use x86_64::structures::paging::page_table::FrameError;
+    use x86_64::registers::control::Cr3;
+
+    // read the active level 4 frame from the CR3 register
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let table_indexes = [
+        addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()
+    ];
+    let mut frame = level_4_table_frame;
+
+    // traverse the multi-level page table
+    for (level, &index) in table_indexes.iter().enumerate() {
+        // convert the frame into a page table reference
+        let virt = physical_memory_offset + frame.start_address().as_u64();
+        let table_ptr: *const PageTable = virt.as_ptr();
+        let table = unsafe {&*table_ptr};
+
+        // read the page table entry and update `frame`
+        let entry = &table[index];
+        frame = match entry.frame() {
+            Ok(frame) => frame,
+            Err(FrameError::FrameNotPresent) => return None,
+            // A huge page maps the rest of the address directly,
+            // so the lower indexes are part of the page offset
+            Err(FrameError::HugeFrame) => {
+                let page_size = match level {
+                    1 => Size1GiB::SIZE,
+                    2 => Size2MiB::SIZE,
+                    _ => return None, // the huge bit is reserved in L4 entries
+                };
+                return Some(entry.addr() + (addr.as_u64() & (page_size - 1)));
+            }
+        };
+    }
+
+    // calculate the physical address by adding the page offset
+    Some(frame.start_address() + u64::from(addr.page_offset()))
+}
+
+
+
+/// Whether the CPU supports 1GiB pages (CPUID.80000001h:EDX.Page1GB)
+pub fn supports_1gib_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(0x8000_0000).eax >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 26) != 0 }
+}
+
+/// Maps the physical region `[phys, phys+size)` at `virt` using the biggest pages possible
+///
+/// 1GiB pages (when supported) and 2MiB pages are used wherever both addresses
+/// are aligned on them and enough of the region is left, 4KiB pages fill the gaps.
+/// Both addresses and `size` must be 4KiB aligned. The frames aren't taken
+/// from the frame allocator: this is meant for memory the kernel already owns
+/// or for device memory.
+///
+/// This function is unsafe because the caller must guarantee that the mapping
+/// doesn't break memory safety, e.g. by aliasing memory used elsewhere.
+pub unsafe fn map_huge_region(mem: &mut KernelMemory, virt: VirtAddr, phys: PhysAddr,
+    size: u64, flags: PageTableFlags) -> Result<(), MapError>
+{
+    assert!(virt.is_aligned(Size4KiB::SIZE) && phys.is_aligned(Size4KiB::SIZE)
+        && size % Size4KiB::SIZE == 0, "huge region must be 4KiB aligned");
+
+    let KernelMemory { mapper, frame_allocator } = mem;
+    let use_1gib = supports_1gib_pages();
+    let mut offset = 0;
+
+    while offset < size {
+        let (v, p, left) = (virt + offset, phys + offset, size - offset);
+        let fits = |page_size: u64| v.is_aligned(page_size) && p.is_aligned(page_size) && left >= page_size;
+
+        offset += if use_1gib && fits(Size1GiB::SIZE) {
+            let page = Page::<Size1GiB>::containing_address(v);
+            let frame = PhysFrame::<Size1GiB>::containing_address(p);
+            mapper.map(page, frame, flags, frame_allocator)?.flush();
+            Size1GiB::SIZE
+        } else if fits(Size2MiB::SIZE) {
+            let page = Page::<Size2MiB>::containing_address(v);
+            let frame = PhysFrame::<Size2MiB>::containing_address(p);
+            mapper.map(page, frame, flags, frame_allocator)?.flush();
+            Size2MiB::SIZE
+        } else {
+            let page = Page::<Size4KiB>::containing_address(v);
+            let frame = PhysFrame::<Size4KiB>::containing_address(p);
+            mapper.map(page, frame, flags, frame_allocator)?.flush();
+            Size4KiB::SIZE
+        };
+    }
+
+    Ok(())
+}
+
+
+
+/// A FrameAllocator that always returns `None`.
+pub struct EmptyFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        None
+    }
+}
+
+/// Creates an example mapping for the given page to frame `0xb8000`.
+pub fn create_example_mapping(
+    page: Page,
+    mapper: &mut PageMapper,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+ 
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+ 
+    let map_to_result = unsafe {
+        // FIXME: this is not safe, we do it only for testing
+        mapper.map(page, frame, flags, frame_allocator)
+    };
+    map_to_result.expect("map_to failed").flush();
+}
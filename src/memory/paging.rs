@@ -0,0 +1,436 @@
+//! Our own implementation of x86_64 paging
+//!
+//! `PageMapper` edits a 4-level page table hierarchy through the physical
+//! memory mapping: it maps, unmaps, changes flags and translates pages of
+//! any size (4KiB, 2MiB and 1GiB), one at a time or by ranges.
+//!
+//! Every operation returns what needs to be flushed from the TLB. Range
+//! operations collect those in a `FlushBatch` which falls back to reloading
+//! CR3 when too many pages were touched.
+//!
+//! Unmapping frees the L1 and L2 tables left empty. L3 tables are never
+//! freed, so that L4 entries stay valid: they are shared by address spaces.
+
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{
+        page_table::PageTableEntry, FrameAllocator, FrameDeallocator, Page, PageSize,
+        PageTable, PageTableFlags as Flags, PageTableIndex, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// A frame was needed for a page table but none was left
+    FrameAllocationFailed,
+    /// The page is (partly) covered by a bigger page
+    ParentEntryHugePage,
+    /// The page is already mapped, to the given address
+    PageAlreadyMapped(PhysAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmapError {
+    PageNotMapped,
+    /// The page is (partly) covered by a bigger page
+    ParentEntryHugePage,
+    /// The page is mapped with smaller pages than asked for
+    NotHugePage,
+}
+
+/// What `translate` found for an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// Physical address the virtual address is mapped to
+    pub addr: PhysAddr,
+    /// Start of the frame containing `addr`
+    pub frame_start: PhysAddr,
+    /// Size of the page mapping the address, in bytes
+    pub page_size: u64,
+    pub flags: Flags,
+}
+
+
+/// A page whose translation changed and must be flushed from the TLB
+#[must_use = "the TLB must be flushed for the change to be visible"]
+pub struct PageFlush(VirtAddr);
+
+impl PageFlush {
+    pub fn flush(self) {
+        tlb::flush(self.0);
+    }
+
+    /// Don't flush, e.g. because the page table isn't the active one
+    pub fn ignore(self) {}
+}
+
+/// Number of pages a `FlushBatch` flushes one by one, more and the whole TLB is flushed
+const BATCH_CAPACITY: usize = 32;
+
+/// A set of pages to flush from the TLB
+#[must_use = "the TLB must be flushed for the changes to be visible"]
+pub struct FlushBatch {
+    pages: [u64; BATCH_CAPACITY],
+    len: usize,
+}
+
+impl FlushBatch {
+    pub const fn new() -> Self {
+        Self { pages: [0; BATCH_CAPACITY], len: 0 }
+    }
+
+    pub fn add(&mut self, flush: PageFlush) {
+        if self.len < BATCH_CAPACITY {
+            self.pages[self.len] = flush.0.as_u64();
+        }
+        self.len += 1;
+    }
+
+    /// Number of pages in the batch
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn flush(self) {
+        if self.len > BATCH_CAPACITY {
+            tlb::flush_all();
+        } else {
+            for &page in &self.pages[..self.len] {
+                tlb::flush(VirtAddr::new(page));
+            }
+        }
+    }
+
+    pub fn ignore(self) {}
+}
+
+
+/// Level of the table whose entries map pages of size `S` directly
+fn leaf_level<S: PageSize>() -> u8 {
+    if S::SIZE == Size1GiB::SIZE { 3 }
+    else if S::SIZE == Size2MiB::SIZE { 2 }
+    else { 1 }
+}
+
+/// Index of `addr` in the table of the given level
+fn index(addr: VirtAddr, level: u8) -> PageTableIndex {
+    match level {
+        4 => addr.p4_index(),
+        3 => addr.p3_index(),
+        2 => addr.p2_index(),
+        _ => addr.p1_index(),
+    }
+}
+
+/// Size of the memory covered by one entry of a table of the given level
+fn entry_size(level: u8) -> u64 {
+    Size4KiB::SIZE << (9 * (level as u64 - 1))
+}
+
+fn is_huge(entry: &PageTableEntry, level: u8) -> bool {
+    (level == 2 || level == 3) && entry.flags().contains(Flags::HUGE_PAGE)
+}
+
+
+/// Edits the page table hierarchy rooted at a level 4 table
+pub struct PageMapper {
+    level_4_frame: PhysFrame,
+    physical_memory_offset: VirtAddr,
+}
+
+impl PageMapper {
+    /// Creates a mapper for the hierarchy rooted at `level_4_frame`
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// complete physical memory is mapped at `physical_memory_offset`, that
+    /// `level_4_frame` holds a valid level 4 table and that it isn't edited
+    /// through another mapper at the same time.
+    pub unsafe fn new(level_4_frame: PhysFrame, physical_memory_offset: VirtAddr) -> Self {
+        Self { level_4_frame, physical_memory_offset }
+    }
+
+    /// Frame of the level 4 table, i.e. what CR3 must hold to use this hierarchy
+    pub fn level_4_frame(&self) -> PhysFrame { self.level_4_frame }
+
+    pub fn physical_memory_offset(&self) -> VirtAddr { self.physical_memory_offset }
+
+    /// The level 4 table
+    pub fn level_4_table(&mut self) -> &mut PageTable {
+        unsafe { self.table(self.level_4_frame) }
+    }
+
+    /// The table stored in `frame`, through the physical memory mapping
+    ///
+    /// Unsafe because the frame must hold a page table and nothing else
+    /// must be referencing it.
+    unsafe fn table(&self, frame: PhysFrame) -> &'static mut PageTable {
+        &mut *(self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Returns the table `entry` points to, creating it if needed
+    unsafe fn next_table_create(&self, entry: &mut PageTableEntry, user: bool,
+        allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<&'static mut PageTable, MapError>
+    {
+        let user_flag = if user { Flags::USER_ACCESSIBLE } else { Flags::empty() };
+
+        if entry.is_unused() {
+            let frame = allocator.allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+            entry.set_frame(frame, Flags::PRESENT | Flags::WRITABLE | user_flag);
+            let table = self.table(frame);
+            table.zero();
+            return Ok(table);
+        }
+        if entry.flags().contains(Flags::HUGE_PAGE) {
+            return Err(MapError::ParentEntryHugePage);
+        }
+        // The user bit must be set at every level for user pages to be accessible
+        if user && !entry.flags().contains(Flags::USER_ACCESSIBLE) {
+            entry.set_flags(entry.flags() | Flags::USER_ACCESSIBLE);
+        }
+        Ok(self.table(PhysFrame::containing_address(entry.addr())))
+    }
+
+    /// Frames of the tables leading to the entry of `addr` at level `leaf`,
+    /// indexed by level (`tables[4]` is the level 4 table)
+    fn path(&self, addr: VirtAddr, leaf: u8) -> Result<[Option<PhysFrame>; 5], UnmapError> {
+        let mut tables = [None; 5];
+        let mut frame = self.level_4_frame;
+
+        for level in (leaf..=4).rev() {
+            tables[level as usize] = Some(frame);
+            if level == leaf { break; }
+
+            let entry = &unsafe { self.table(frame) }[index(addr, level)];
+            if !entry.flags().contains(Flags::PRESENT) {
+                return Err(UnmapError::PageNotMapped);
+            }
+            if is_huge(entry, level) {
+                return Err(UnmapError::ParentEntryHugePage);
+            }
+            frame = PhysFrame::containing_address(entry.addr());
+        }
+        Ok(tables)
+    }
+
+    /// The entry mapping `page`, checking it maps a page of the right size
+    fn leaf_entry<S: PageSize>(&self, page: Page<S>) -> Result<&'static mut PageTableEntry, UnmapError> {
+        let (addr, leaf) = (page.start_address(), leaf_level::<S>());
+        let table = self.path(addr, leaf)?[leaf as usize].unwrap();
+        let entry = &mut unsafe { self.table(table) }[index(addr, leaf)];
+
+        if !entry.flags().contains(Flags::PRESENT) {
+            return Err(UnmapError::PageNotMapped);
+        }
+        if leaf > 1 && !entry.flags().contains(Flags::HUGE_PAGE) {
+            return Err(UnmapError::NotHugePage);
+        }
+        Ok(entry)
+    }
+
+
+    /// Maps `page` to `frame`, creating the intermediate tables as needed
+    ///
+    /// `HUGE_PAGE` is added to the flags of 2MiB and 1GiB pages.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// mapping doesn't break memory safety, e.g. by aliasing memory used elsewhere.
+    pub unsafe fn map<S: PageSize>(&mut self, page: Page<S>, frame: PhysFrame<S>, flags: Flags,
+        allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<PageFlush, MapError>
+    {
+        let (addr, leaf) = (page.start_address(), leaf_level::<S>());
+        let user = flags.contains(Flags::USER_ACCESSIBLE);
+
+        let mut table = self.table(self.level_4_frame);
+        for level in (leaf + 1..=4).rev() {
+            table = self.next_table_create(&mut table[index(addr, level)], user, allocator)?;
+        }
+
+        let entry = &mut table[index(addr, leaf)];
+        if !entry.is_unused() {
+            return Err(MapError::PageAlreadyMapped(entry.addr()));
+        }
+
+        let flags = if leaf > 1 { flags | Flags::HUGE_PAGE } else { flags };
+        entry.set_addr(frame.start_address(), flags);
+        Ok(PageFlush(addr))
+    }
+
+    /// Unmaps `page` and returns the frame it was mapped to
+    ///
+    /// The frame itself isn't freed, but the L1/L2 tables left empty are given
+    /// back to `deallocator`.
+    pub fn unmap<S: PageSize>(&mut self, page: Page<S>,
+        deallocator: &mut impl FrameDeallocator<Size4KiB>) -> Result<(PhysFrame<S>, PageFlush), UnmapError>
+    {
+        let (addr, leaf) = (page.start_address(), leaf_level::<S>());
+        let tables = self.path(addr, leaf)?;
+        let entry = self.leaf_entry(page)?;
+
+        let frame = PhysFrame::containing_address(entry.addr());
+        entry.set_unused();
+
+        // Free the tables left empty, from the bottom up
+        for level in leaf..3 {
+            let table_frame = tables[level as usize].unwrap();
+            if !unsafe { self.table(table_frame) }.iter().all(|e| e.is_unused()) {
+                break;
+            }
+            let parent = unsafe { self.table(tables[level as usize + 1].unwrap()) };
+            parent[index(addr, level + 1)].set_unused();
+            unsafe { deallocator.deallocate_frame(table_frame) };
+        }
+
+        Ok((frame, PageFlush(addr)))
+    }
+
+    /// Replaces the flags of an already mapped page
+    pub fn update_flags<S: PageSize>(&mut self, page: Page<S>, flags: Flags)
+        -> Result<PageFlush, UnmapError>
+    {
+        let entry = self.leaf_entry(page)?;
+        let flags = if leaf_level::<S>() > 1 { flags | Flags::HUGE_PAGE } else { flags };
+        entry.set_flags(flags);
+        Ok(PageFlush(page.start_address()))
+    }
+
+    /// Maps an already mapped page to another frame, returning the previous one
+    ///
+    /// This function is unsafe for the same reasons as `map`.
+    pub unsafe fn remap<S: PageSize>(&mut self, page: Page<S>, frame: PhysFrame<S>, flags: Flags)
+        -> Result<(PhysFrame<S>, PageFlush), UnmapError>
+    {
+        let entry = self.leaf_entry(page)?;
+        let previous = PhysFrame::containing_address(entry.addr());
+        let flags = if leaf_level::<S>() > 1 { flags | Flags::HUGE_PAGE } else { flags };
+        entry.set_addr(frame.start_address(), flags);
+        Ok((previous, PageFlush(page.start_address())))
+    }
+
+    /// Translates a virtual address, whatever the size of the page mapping it
+    pub fn translate(&self, addr: VirtAddr) -> Option<Translation> {
+        let mut frame = self.level_4_frame;
+
+        for level in (1..=4).rev() {
+            let entry = &unsafe { self.table(frame) }[index(addr, level)];
+            if !entry.flags().contains(Flags::PRESENT) {
+                return None;
+            }
+            if level == 1 || is_huge(entry, level) {
+                let page_size = entry_size(level);
+                return Some(Translation {
+                    addr: entry.addr() + (addr.as_u64() & (page_size - 1)),
+                    frame_start: entry.addr(),
+                    page_size,
+                    flags: entry.flags(),
+                });
+            }
+            frame = PhysFrame::containing_address(entry.addr());
+        }
+        unreachable!()
+    }
+
+
+    /// Maps every page of `pages` to a newly allocated frame
+    ///
+    /// On error, the pages mapped before are unmapped and their frames freed:
+    /// either all the pages are mapped or none is.
+    pub unsafe fn map_range<S: PageSize, I, A>(&mut self, pages: I, flags: Flags, allocator: &mut A)
+        -> Result<FlushBatch, MapError>
+    where
+        I: IntoIterator<Item = Page<S>>,
+        I::IntoIter: Clone,
+        A: FrameAllocator<Size4KiB> + FrameAllocator<S> + FrameDeallocator<Size4KiB> + FrameDeallocator<S>,
+    {
+        let pages = pages.into_iter();
+        let mut batch = FlushBatch::new();
+        for (mapped, page) in pages.clone().enumerate() {
+            match self.map_new_frame(page, flags, allocator) {
+                Ok(flush) => batch.add(flush),
+                Err(err) => {
+                    self.unmap_mapped(pages.take(mapped), allocator);
+                    batch.flush();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Maps `page` to a newly allocated frame, which is freed on error
+    unsafe fn map_new_frame<S: PageSize, A>(&mut self, page: Page<S>, flags: Flags, allocator: &mut A)
+        -> Result<PageFlush, MapError>
+    where
+        A: FrameAllocator<Size4KiB> + FrameAllocator<S> + FrameDeallocator<S>,
+    {
+        let frame = FrameAllocator::<S>::allocate_frame(allocator)
+            .ok_or(MapError::FrameAllocationFailed)?;
+        let result = self.map(page, frame, flags, allocator);
+        if result.is_err() {
+            FrameDeallocator::<S>::deallocate_frame(allocator, frame);
+        }
+        result
+    }
+
+    /// Unmaps pages `map_range` just mapped and frees their frames, the
+    /// caller flushes the TLB
+    unsafe fn unmap_mapped<S: PageSize, D>(&mut self, pages: impl Iterator<Item = Page<S>>, deallocator: &mut D)
+    where
+        D: FrameDeallocator<Size4KiB> + FrameDeallocator<S>,
+    {
+        for page in pages {
+            let (frame, flush) = self.unmap(page, deallocator)
+                .expect("a page map_range mapped is gone");
+            FrameDeallocator::<S>::deallocate_frame(deallocator, frame);
+            flush.ignore();
+        }
+    }
+
+    /// Unmaps every page of `pages` and gives their frames back to `deallocator`
+    ///
+    /// On error, the pages before stay unmapped and are flushed from the TLB.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// frames are owned by the mapping and not used anymore.
+    pub unsafe fn unmap_range<S: PageSize, D>(&mut self, pages: impl IntoIterator<Item = Page<S>>,
+        deallocator: &mut D) -> Result<FlushBatch, UnmapError>
+    where
+        D: FrameDeallocator<Size4KiB> + FrameDeallocator<S>,
+    {
+        let mut batch = FlushBatch::new();
+        for page in pages {
+            match self.unmap(page, deallocator) {
+                Ok((frame, flush)) => {
+                    FrameDeallocator::<S>::deallocate_frame(deallocator, frame);
+                    batch.add(flush);
+                }
+                Err(err) => {
+                    batch.flush();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Replaces the flags of every page of `pages`
+    ///
+    /// On error, the pages before keep their new flags and are flushed from
+    /// the TLB.
+    pub fn update_flags_range<S: PageSize>(&mut self, pages: impl IntoIterator<Item = Page<S>>,
+        flags: Flags) -> Result<FlushBatch, UnmapError>
+    {
+        let mut batch = FlushBatch::new();
+        for page in pages {
+            match self.update_flags(page, flags) {
+                Ok(flush) => batch.add(flush),
+                Err(err) => {
+                    batch.flush();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(batch)
+    }
+}
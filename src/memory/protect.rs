@@ -0,0 +1,257 @@
+//! W^X for kernel mappings
+//!
+//! No kernel page is both writable and executable: `enable_nx` turns on the
+//! no-execute bit (EFER.NXE) and `protect_kernel` remaps the kernel's own
+//! segments from its ELF program headers, text as read-only and executable,
+//! read-only data as read-only and NX, data and bss as writable and NX. The
+//! heap, the kernel stacks and the physical memory mapping are NX too.
+//!
+//! `diagnose` explains the page faults caused by these protections.
+
+use core::fmt;
+
+use x86_64::{
+    instructions::tlb,
+    registers::model_specific::{Efer, EferFlags},
+    structures::idt::PageFaultErrorCode,
+    structures::paging::{Page, PageTableFlags as Flags, Size4KiB},
+    VirtAddr,
+};
+
+use super::{physical_memory_offset, stack, with_kernel_memory};
+use crate::allocator;
+
+/// Sets EFER.NXE, without it `NO_EXECUTE` is a reserved bit
+pub fn enable_nx() {
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+}
+
+/// The flags to actually map a page with: writable pages are never executable
+pub fn wx(flags: Flags) -> Flags {
+    if flags.contains(Flags::WRITABLE) {
+        flags | Flags::NO_EXECUTE
+    } else {
+        flags
+    }
+}
+
+
+/// What a kernel segment holds, from its ELF flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// Executable
+    Text,
+    /// Neither executable nor writable
+    Rodata,
+    /// Writable: data, bss...
+    Data,
+}
+
+impl SegmentKind {
+    /// Flags of the pages of the segment
+    fn flags(self) -> Flags {
+        match self {
+            SegmentKind::Text => Flags::PRESENT,
+            SegmentKind::Rodata => Flags::PRESENT | Flags::NO_EXECUTE,
+            SegmentKind::Data => Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+        }
+    }
+}
+
+/// A loaded segment of the kernel image
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub start: VirtAddr,
+    /// End of the segment in memory, bss included
+    pub end: VirtAddr,
+    pub kind: SegmentKind,
+}
+
+impl Segment {
+    fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+extern "C" {
+    /// Defined by the linker (lld) when the ELF header is part of the first
+    /// loaded segment, which it is with the default layout
+    static __ehdr_start: ElfHeader;
+}
+
+/// The loaded segments of the kernel, read from its own program headers
+pub fn kernel_segments() -> impl Iterator<Item = Segment> {
+    let header = unsafe { &__ehdr_start };
+    let valid = header.ident[..4] == *b"\x7fELF"
+        && header.phentsize as usize == core::mem::size_of::<ProgramHeader>();
+
+    let program_headers: &[ProgramHeader] = if valid {
+        unsafe {
+            let first = (header as *const ElfHeader as *const u8).add(header.phoff as usize);
+            core::slice::from_raw_parts(first as *const ProgramHeader, header.phnum as usize)
+        }
+    } else {
+        &[]
+    };
+
+    program_headers.iter()
+        .filter(|ph| ph.kind == PT_LOAD && ph.memsz != 0)
+        .map(|ph| Segment {
+            start: VirtAddr::new(ph.vaddr),
+            end: VirtAddr::new(ph.vaddr + ph.memsz),
+            kind: if ph.flags & PF_X != 0 {
+                SegmentKind::Text
+            } else if ph.flags & PF_W != 0 {
+                SegmentKind::Data
+            } else {
+                SegmentKind::Rodata
+            },
+        })
+}
+
+/// Remaps the kernel segments W^X and makes the physical memory mapping NX
+///
+/// Must be called once the kernel memory is installed, `enable_nx` must
+/// have been called before (`memory::init` does).
+pub fn protect_kernel() {
+    with_kernel_memory(|mem| {
+        for segment in kernel_segments() {
+            let first = Page::<Size4KiB>::containing_address(segment.start);
+            let last = Page::<Size4KiB>::containing_address(segment.end - 1u64);
+
+            for page in Page::range_inclusive(first, last) {
+                let translation = match mem.mapper.translate(page.start_address()) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                let flags = translation.flags - Flags::WRITABLE - Flags::NO_EXECUTE;
+                mem.mapper.update_flags(page, flags | segment.kind.flags())
+                    .expect("kernel segment mapped with huge pages")
+                    .ignore();
+            }
+        }
+
+        // NX on the L4 entry covers the whole physical memory mapping, as
+        // long as the kernel doesn't share it
+        let index = physical_memory_offset().p4_index();
+        if kernel_segments().all(|s| s.start.p4_index() != index) {
+            let entry = &mut mem.mapper.level_4_table()[index];
+            entry.set_flags(entry.flags() | Flags::NO_EXECUTE);
+        }
+    });
+    tlb::flush_all();
+}
+
+
+/// What a faulting access went against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// Write to a read-only page
+    Write,
+    /// Instruction fetch from a no-execute page
+    Execute,
+}
+
+/// Where the faulting address is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    Kernel(SegmentKind),
+    Heap,
+    KernelStack,
+    PhysicalMemory,
+    Other,
+}
+
+/// A page fault caused by a protection, see `diagnose`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub violation: Violation,
+    pub area: Area,
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.violation {
+            Violation::Write => write!(f, "write to read-only memory")?,
+            Violation::Execute => write!(f, "execution of non-executable memory")?,
+        }
+        match self.area {
+            Area::Kernel(SegmentKind::Text) => write!(f, " (kernel text)"),
+            Area::Kernel(SegmentKind::Rodata) => write!(f, " (kernel read-only data)"),
+            Area::Kernel(SegmentKind::Data) => write!(f, " (kernel data)"),
+            Area::Heap => write!(f, " (heap)"),
+            Area::KernelStack => write!(f, " (kernel stack)"),
+            Area::PhysicalMemory => write!(f, " (physical memory mapping)"),
+            Area::Other => Ok(()),
+        }
+    }
+}
+
+/// Explains a page fault at `addr`, `None` if it wasn't caused by a write
+/// or execute protection
+///
+/// Doesn't take any lock, so the page fault handler can use it.
+pub fn diagnose(addr: VirtAddr, error_code: PageFaultErrorCode) -> Option<Diagnosis> {
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return None;
+    }
+    let violation = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        Violation::Execute
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        Violation::Write
+    } else {
+        return None;
+    };
+
+    let a = addr.as_u64();
+    // The heap may be locked, use the range reserved for it
+    let heap = allocator::HEAP_START as u64..(allocator::HEAP_START + allocator::HEAP_MAX_SIZE) as u64;
+    let offset = physical_memory_offset();
+    let area = if let Some(segment) = kernel_segments().find(|s| s.contains(addr)) {
+        Area::Kernel(segment.kind)
+    } else if heap.contains(&a) {
+        Area::Heap
+    } else if (stack::KERNEL_STACKS_START..stack::KERNEL_STACKS_END).contains(&a) {
+        Area::KernelStack
+    } else if offset.as_u64() != 0 && addr.p4_index() == offset.p4_index() {
+        Area::PhysicalMemory
+    } else {
+        Area::Other
+    };
+
+    Some(Diagnosis { violation, area })
+}
@@ -0,0 +1,141 @@
+//! Kernel stacks
+//!
+//! Stacks are carved from their own region of the kernel address space,
+//! each one with an unmapped guard page right below it: overflowing a stack
+//! page faults instead of silently overwriting whatever is below. Since the
+//! CPU can't push the page fault frame on the overflowed stack either, this
+//! ends in a double fault, whose handler uses `is_guard_page` to tell what
+//! happened.
+//!
+//! The virtual ranges of freed stacks are never reused, so the region is
+//! large enough for plenty of stacks to come and go.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::{
+    structures::paging::{Page, PageSize, PageTableFlags as Flags, Size4KiB},
+    VirtAddr,
+};
+
+use super::paging::MapError;
+use super::{with_kernel_memory, KernelMemory};
+
+/// Start of the kernel stacks region
+pub const KERNEL_STACKS_START: u64 = 0x_5555_0000_0000;
+/// End of the kernel stacks region, 64GiB later
+pub const KERNEL_STACKS_END: u64 = KERNEL_STACKS_START + (64 << 30);
+
+/// Next free address of the region
+static NEXT: AtomicU64 = AtomicU64::new(KERNEL_STACKS_START);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// The kernel stacks region is exhausted
+    OutOfVirtualSpace,
+    Map(MapError),
+}
+
+impl From<MapError> for StackError {
+    fn from(err: MapError) -> Self {
+        StackError::Map(err)
+    }
+}
+
+/// A mapped kernel stack with a guard page below, unmapped when dropped
+#[derive(Debug)]
+pub struct KernelStack {
+    /// First mapped page, the guard page is the one before
+    bottom: Page,
+    pages: u64,
+}
+
+impl KernelStack {
+    /// Allocates a stack of at least `size` bytes (rounded up to pages)
+    pub fn new(size: usize) -> Result<Self, StackError> {
+        let pages = ((size as u64 + Size4KiB::SIZE - 1) / Size4KiB::SIZE).max(1);
+        let span = (pages + 1) * Size4KiB::SIZE;
+
+        let start = NEXT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(span).filter(|&end| end <= KERNEL_STACKS_END)
+        }).map_err(|_| StackError::OutOfVirtualSpace)?;
+
+        let bottom = Page::containing_address(VirtAddr::new(start)) + 1;
+        let pages_range = Page::range(bottom, bottom + pages);
+
+        let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+        let result = with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+            unsafe { mapper.map_range(pages_range, flags, frame_allocator) }.map(|batch| batch.flush())
+        });
+        if let Err(err) = result {
+            // Nothing is mapped, give the range back unless another stack came after
+            let _ = NEXT.compare_exchange(start + span, start, Ordering::Relaxed, Ordering::Relaxed);
+            return Err(err.into());
+        }
+
+        Ok(KernelStack { bottom, pages })
+    }
+
+    fn page_range(&self) -> impl Iterator<Item = Page> {
+        Page::range(self.bottom, self.bottom + self.pages)
+    }
+
+    /// Initial stack pointer, the stack grows down from here
+    pub fn top(&self) -> VirtAddr {
+        (self.bottom + self.pages).start_address()
+    }
+
+    /// Lowest usable address
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom.start_address()
+    }
+
+    /// The unmapped page right below the stack
+    pub fn guard_page(&self) -> Page {
+        self.bottom - 1
+    }
+
+    pub fn size(&self) -> usize {
+        (self.pages * Size4KiB::SIZE) as usize
+    }
+
+    /// Keeps the stack mapped forever, e.g. for the IST stacks
+    pub fn leak(self) -> VirtAddr {
+        let top = self.top();
+        core::mem::forget(self);
+        top
+    }
+}
+
+impl Drop for KernelStack {
+    /// Unmaps the stack and frees its frames
+    ///
+    /// Obviously the stack must not be in use anymore.
+    fn drop(&mut self) {
+        let pages = self.page_range();
+        with_kernel_memory(|KernelMemory { mapper, frame_allocator }| unsafe {
+            mapper.unmap_range(pages, frame_allocator)
+                .expect("kernel stack not mapped")
+                .flush();
+        });
+    }
+}
+
+
+/// Whether `addr` is in the guard page of a live kernel stack
+///
+/// Doesn't take the kernel memory lock, so it can be called from the double
+/// fault handler whatever was interrupted.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    if !(KERNEL_STACKS_START..NEXT.load(Ordering::Relaxed)).contains(&addr) {
+        return false;
+    }
+
+    // The guard page is unmapped and directly followed by the (mapped) stack
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let offset = super::physical_memory_offset();
+    unsafe {
+        super::translate_addr(page.start_address(), offset).is_none()
+            && super::translate_addr((page + 1).start_address(), offset).is_some()
+    }
+}
@@ -0,0 +1,214 @@
+//! Kernel virtual memory allocator
+//!
+//! Hands out ranges of a dedicated region of the kernel address space:
+//! `vmalloc` maps frames taken one by one (so physically discontiguous) to
+//! a contiguous virtual range, `ioremap` maps a physical range, e.g. the
+//! registers of a device, uncached. Both return a `VmArea` which unmaps
+//! the range (and frees the frames it owns) when dropped.
+//!
+//! Every area is followed by an unmapped guard page. The free ranges are
+//! kept in a fixed size table rather than on the heap, which takes the
+//! kernel memory lock to grow.
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{Page, PageSize, PageTableFlags as Flags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::paging::{FlushBatch, MapError};
+use super::{with_kernel_memory, KernelMemory};
+
+/// Start of the vmalloc region
+pub const VMALLOC_START: u64 = 0x_6666_0000_0000;
+/// End of the vmalloc region, 64GiB later
+pub const VMALLOC_END: u64 = VMALLOC_START + (64 << 30);
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    /// No free range is big enough
+    OutOfVirtualSpace,
+    Map(MapError),
+}
+
+impl From<MapError> for VmError {
+    fn from(err: MapError) -> Self {
+        VmError::Map(err)
+    }
+}
+
+
+/// Maximum number of free ranges, the region fragmenting more than that
+/// leaks the ranges freed
+const MAX_HOLES: usize = 64;
+
+/// Free ranges of the region, sorted and never adjacent
+struct RangeAllocator {
+    holes: [(u64, u64); MAX_HOLES],
+    len: usize,
+}
+
+impl RangeAllocator {
+    const fn new() -> Self {
+        let mut holes = [(0, 0); MAX_HOLES];
+        holes[0] = (VMALLOC_START, VMALLOC_END);
+        RangeAllocator { holes, len: 1 }
+    }
+
+    /// First fit
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let i = (0..self.len).find(|&i| self.holes[i].1 - self.holes[i].0 >= size)?;
+        let start = self.holes[i].0;
+        self.holes[i].0 += size;
+        if self.holes[i].0 == self.holes[i].1 {
+            self.holes.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+        Some(start)
+    }
+
+    fn free(&mut self, start: u64, size: u64) {
+        let end = start + size;
+        let i = (0..self.len).find(|&i| self.holes[i].0 >= end).unwrap_or(self.len);
+
+        let merge_prev = i > 0 && self.holes[i - 1].1 == start;
+        let merge_next = i < self.len && self.holes[i].0 == end;
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.holes[i - 1].1 = self.holes[i].1;
+                self.holes.copy_within(i + 1..self.len, i);
+                self.len -= 1;
+            }
+            (true, false) => self.holes[i - 1].1 = end,
+            (false, true) => self.holes[i].0 = start,
+            (false, false) => {
+                if self.len == MAX_HOLES {
+                    return;
+                }
+                self.holes.copy_within(i..self.len, i + 1);
+                self.holes[i] = (start, end);
+                self.len += 1;
+            }
+        }
+    }
+}
+
+static RANGES: Mutex<RangeAllocator> = Mutex::new(RangeAllocator::new());
+
+
+/// A mapped range of the vmalloc region, unmapped when dropped
+#[derive(Debug)]
+pub struct VmArea {
+    /// First page of the range
+    first: Page,
+    pages: u64,
+    /// Offset of the start of the area in the first page
+    offset: u64,
+    size: u64,
+    /// Whether the frames were allocated for the area, and must be freed
+    owns_frames: bool,
+}
+
+impl VmArea {
+    pub fn start(&self) -> VirtAddr {
+        self.first.start_address() + self.offset
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    pub fn as_ptr<T>(&self) -> *const T {
+        self.start().as_ptr()
+    }
+
+    pub fn as_mut_ptr<T>(&mut self) -> *mut T {
+        self.start().as_mut_ptr()
+    }
+
+    fn page_range(&self) -> impl Iterator<Item = Page> {
+        Page::range(self.first, self.first + self.pages)
+    }
+}
+
+impl Drop for VmArea {
+    fn drop(&mut self) {
+        let (pages, owns_frames) = (self.page_range(), self.owns_frames);
+        with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+            let mut batch = FlushBatch::new();
+            for page in pages {
+                // Pages may be missing if the area couldn't be mapped entirely
+                if let Ok((frame, flush)) = mapper.unmap(page, frame_allocator) {
+                    if owns_frames {
+                        unsafe { frame_allocator.free(frame, 0) };
+                    }
+                    batch.add(flush);
+                }
+            }
+            batch.flush();
+        });
+
+        // The guard page goes back too
+        RANGES.lock().free(self.first.start_address().as_u64(), (self.pages + 1) * PAGE_SIZE);
+    }
+}
+
+
+/// Reserves a range of `pages` pages, followed by a guard page
+fn reserve(pages: u64) -> Result<Page, VmError> {
+    let start = RANGES.lock().allocate((pages + 1) * PAGE_SIZE).ok_or(VmError::OutOfVirtualSpace)?;
+    Ok(Page::containing_address(VirtAddr::new(start)))
+}
+
+/// Maps `size` bytes (rounded up to pages) of newly allocated frames
+///
+/// The memory isn't zeroed.
+pub fn vmalloc(size: usize) -> Result<VmArea, VmError> {
+    let pages = ((size as u64 + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    let area = VmArea {
+        first: reserve(pages)?,
+        pages,
+        offset: 0,
+        size: pages * PAGE_SIZE,
+        owns_frames: true,
+    };
+
+    // On error, dropping the area frees what was mapped
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+    with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        unsafe { mapper.map_range(area.page_range(), flags, frame_allocator) }.map(|batch| batch.flush())
+    })?;
+    Ok(area)
+}
+
+/// Maps the physical range `phys..phys + size`, uncached, e.g. the registers of a device
+///
+/// This function is unsafe because the caller must guarantee that the range
+/// doesn't alias memory used as something else.
+pub unsafe fn ioremap(phys: PhysAddr, size: usize) -> Result<VmArea, VmError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let offset = phys - first_frame.start_address();
+    let pages = ((offset + size as u64 + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    let area = VmArea {
+        first: reserve(pages)?,
+        pages,
+        offset,
+        size: size as u64,
+        owns_frames: false,
+    };
+
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH
+        | Flags::NO_EXECUTE;
+    with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let mut batch = FlushBatch::new();
+        for (i, page) in area.page_range().enumerate() {
+            let frame = first_frame + i as u64;
+            batch.add(mapper.map(page, frame, flags, frame_allocator)?);
+        }
+        batch.flush();
+        Ok::<(), MapError>(())
+    })?;
+    Ok(area)
+}
@@ -0,0 +1,170 @@
+//! Deferred interrupt work, the bottom halves of the IRQ handlers
+//!
+//! An interrupt handler must be short: it can't block, allocate or print.
+//! It either enqueues a small work item with `defer`, run later by the
+//! `Executor` outside of interrupt context, or signals an `IrqEvent` which
+//! a task is waiting on.
+//!
+//! Nothing here allocates once `init` was called. When the work queue, or
+//! the queue of a driver, is full the work is dropped and counted as an
+//! overflow, see `stats`.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+
+/// Capacity of the work queue
+pub const WORK_QUEUE_LEN: usize = 256;
+
+/// A bottom half, called with the argument given to `defer`
+pub type Work = fn(arg: usize);
+
+static WORK_QUEUE: OnceCell<ArrayQueue<(Work, usize)>> = OnceCell::uninit();
+
+static DEFERRED: AtomicU64 = AtomicU64::new(0);
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+static OVERFLOWS: AtomicU64 = AtomicU64::new(0);
+
+/// Allocates the work queue, work deferred before is dropped
+///
+/// Called by `Executor::new`, calling it again does nothing.
+pub fn init() {
+    let _ = WORK_QUEUE.try_init_once(|| ArrayQueue::new(WORK_QUEUE_LEN));
+}
+
+/// Queues `work` to be called with `arg` by the executor
+///
+/// Safe to call from an interrupt handler. Returns false, and counts an
+/// overflow, if the queue is full or not allocated yet.
+pub fn defer(work: Work, arg: usize) -> bool {
+    let queued = WORK_QUEUE.try_get()
+        .map_or(false, |queue| queue.push((work, arg)).is_ok());
+    if queued {
+        DEFERRED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        record_overflow();
+    }
+    queued
+}
+
+/// Counts input dropped by a driver whose own queue is full
+pub fn record_overflow() {
+    OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether some work is waiting to be run
+pub fn has_pending() -> bool {
+    WORK_QUEUE.try_get().map_or(false, |queue| !queue.is_empty())
+}
+
+/// Runs the work queued so far, returns how many items were run
+///
+/// Work deferred meanwhile, by an interrupt or by the work itself, waits
+/// for the next call: a steady stream of work can't keep the executor from
+/// running its tasks.
+pub fn run_pending() -> usize {
+    let queue = match WORK_QUEUE.try_get() {
+        Ok(queue) => queue,
+        Err(_) => return 0,
+    };
+
+    let mut count = 0;
+    for _ in 0..queue.len() {
+        match queue.pop() {
+            Ok((work, arg)) => work(arg),
+            Err(_) => break,
+        }
+        count += 1;
+    }
+    COMPLETED.fetch_add(count as u64, Ordering::Relaxed);
+    count
+}
+
+
+/// Snapshot of the deferred work counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeferredStats {
+    /// Work items queued
+    pub deferred: u64,
+    /// Work items run
+    pub completed: u64,
+    /// Work items and driver input dropped because a queue was full
+    pub overflows: u64,
+}
+
+pub fn stats() -> DeferredStats {
+    DeferredStats {
+        deferred: DEFERRED.load(Ordering::Relaxed),
+        completed: COMPLETED.load(Ordering::Relaxed),
+        overflows: OVERFLOWS.load(Ordering::Relaxed),
+    }
+}
+
+
+/// An event signaled by an interrupt handler, which a task waits on
+///
+/// The signals are counted until the task takes them: several interrupts
+/// between two polls wake the task only once.
+pub struct IrqEvent {
+    pending: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl IrqEvent {
+    pub const fn new() -> Self {
+        IrqEvent { pending: AtomicUsize::new(0), waker: AtomicWaker::new() }
+    }
+
+    /// Safe to call from an interrupt handler
+    pub fn signal(&self) {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+        self.waker.wake();
+    }
+
+    /// Takes the signals received since the last call, without waiting
+    pub fn take(&self) -> usize {
+        self.pending.swap(0, Ordering::AcqRel)
+    }
+
+    /// Takes the signals received, or registers the task to be woken by the next one
+    pub fn poll_wait(&self, cx: &mut Context) -> Poll<usize> {
+        // fast path
+        let signals = self.take();
+        if signals != 0 {
+            return Poll::Ready(signals);
+        }
+
+        self.waker.register(cx.waker());
+
+        match self.take() {
+            0 => Poll::Pending,
+            signals => {
+                self.waker.take();
+                Poll::Ready(signals)
+            }
+        }
+    }
+
+    /// Waits for a signal, resolves to the number of signals received
+    pub fn wait(&self) -> Wait<'_> {
+        Wait { event: self }
+    }
+}
+
+/// The future returned by `IrqEvent::wait`
+pub struct Wait<'a> {
+    event: &'a IrqEvent,
+}
+
+impl Future for Wait<'_> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+        self.event.poll_wait(cx)
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Task, TaskId};
+use super::{deferred, Task, TaskId};
 use alloc::{collections::BTreeMap, sync::Arc};
 use core::task::Waker;
 use crossbeam_queue::ArrayQueue;
@@ -14,6 +14,7 @@ pub struct Executor {
 
 impl Executor {
     pub fn new() -> Self {
+        deferred::init();
         Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
@@ -59,14 +60,35 @@ impl Executor {
 
     pub fn run(&mut self) -> !{
         loop { 
+            deferred::run_pending();
             self.run_ready_tasks(); 
             self.sleep();
         }
     }
 
+    /// Runs the tasks until all of them are done
+    pub fn run_until_complete(&mut self) {
+        while !self.tasks.is_empty() {
+            deferred::run_pending();
+            self.run_ready_tasks();
+            if !self.tasks.is_empty() {
+                self.sleep();
+            }
+        }
+    }
+
+    /// Halts until the next interrupt if there is nothing to do
+    ///
+    /// Interrupts are disabled while checking, so that work queued by an
+    /// interrupt right after the check isn't left waiting for the next one.
     fn sleep(&mut self){
-        if self.task_queue.is_empty() {
-            x86_64::instructions::hlt();
+        use x86_64::instructions::interrupts;
+
+        interrupts::disable();
+        if self.task_queue.is_empty() && !deferred::has_pending() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
         }
     }
 }
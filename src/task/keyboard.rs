@@ -5,22 +5,20 @@ use crossbeam_queue::ArrayQueue;
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
 
-use crate::println;
+use super::deferred::{self, IrqEvent};
+
+/// Signaled for every scancode queued
+static SCANCODE_EVENT: IrqEvent = IrqEvent::new();
 
 /// Called by the keyboard interrupt handler
 ///
-/// Must not block or allocate.
+/// Must not block or allocate. Scancodes which don't fit in the queue, or
+/// which come before it is allocated, are counted as overflows.
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake();
-        }
-    } else {
-        println!("WARNING: scancode queue uninitialized");
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) if queue.push(scancode).is_ok() => SCANCODE_EVENT.signal(),
+        _ => deferred::record_overflow(),
     }
-
 }
 
 
@@ -47,30 +45,20 @@ impl Stream for ScancodeStream {
         let queue = SCANCODE_QUEUE.try_get().expect("not initialized");
 
 
-        // fast path
-        if let Ok(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
-
-        WAKER.register(cx.waker());
-
-        match queue.pop(){
-            Ok(scancode) => {
-                WAKER.take();
-                Poll::Ready(Some(scancode))
+        loop {
+            if let Ok(scancode) = queue.pop() {
+                return Poll::Ready(Some(scancode));
+            }
+            // A signal may be for a scancode popped already, or for one
+            // pushed right after the pop: look at the queue again
+            if SCANCODE_EVENT.poll_wait(cx).is_pending() {
+                return Poll::Pending;
             }
-            Err(crossbeam_queue::PopError) => Poll::Pending
         }
     }
 }
 
 
-
-use futures_util::task::AtomicWaker;
-
-static WAKER: AtomicWaker = AtomicWaker::new();
-
-
 use futures_util::stream::StreamExt;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use crate::print;
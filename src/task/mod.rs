@@ -1,3 +1,4 @@
+pub mod deferred;
 pub mod executor;
 pub mod simple_executor;
 pub mod keyboard;
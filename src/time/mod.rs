@@ -0,0 +1,169 @@
+//! Monotonic time, counted by the timer interrupt
+//!
+//! Channel 0 of the PIT raises IRQ 0 every `reload` cycles of its 1.193182
+//! MHz clock. Every tick adds the reload value to a counter of PIT cycles,
+//! which is converted to nanoseconds when read: changing the frequency
+//! doesn't change the time already elapsed. The resolution is the period of
+//! the timer.
+//!
+//! Tasks wait for time to pass with `sleep`, `sleep_until` and `timeout`,
+//! see `timer`.
+//!
+//! Until `init` programs the channel, the firmware's setting is assumed:
+//! a reload of 65536, about 18.2 Hz.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+pub use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::arch::pit::{Channel, OperatingMode, PIT_FREQUENCY};
+
+pub mod timer;
+
+pub use self::timer::{sleep, sleep_until, timeout, Elapsed, Sleep, Timeout};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Timer interrupts received
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// PIT cycles elapsed until the last tick
+static CYCLES: AtomicU64 = AtomicU64::new(0);
+/// PIT cycles between two ticks
+static RELOAD: AtomicU32 = AtomicU32::new(1 << 16);
+
+static CHANNEL0: Mutex<Channel> = Mutex::new(unsafe { Channel::new(0) });
+
+/// Programs the timer to interrupt `hz` times per second
+pub fn init(hz: u32) {
+    without_interrupts(|| {
+        let mut channel = CHANNEL0.lock();
+        channel.set_frequency(OperatingMode::RateGenerator, hz);
+        let reload = match channel.reload_value() {
+            Some(0) | None => 1 << 16,
+            Some(reload) => reload as u32,
+        };
+        RELOAD.store(reload, Ordering::Relaxed);
+    });
+}
+
+/// Called by the timer interrupt handler
+pub(crate) fn tick() {
+    CYCLES.fetch_add(RELOAD.load(Ordering::Relaxed) as u64, Ordering::AcqRel);
+    TICKS.fetch_add(1, Ordering::AcqRel);
+    timer::advance(Instant::now());
+}
+
+/// Timer interrupts received since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Acquire)
+}
+
+/// Time between two timer interrupts
+pub fn tick_period() -> Duration {
+    Duration::from_nanos(cycles_to_nanos(RELOAD.load(Ordering::Relaxed) as u64))
+}
+
+/// Time elapsed since boot, or rather since the timer started ticking
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant::BOOT)
+}
+
+/// Converts PIT cycles to nanoseconds, without overflowing for 500 years
+fn cycles_to_nanos(cycles: u64) -> u64 {
+    let frequency = PIT_FREQUENCY as u64;
+    let secs = cycles / frequency;
+    let rest = cycles % frequency;
+    secs * NANOS_PER_SEC + rest * NANOS_PER_SEC / frequency
+}
+
+
+/// A point in time, in nanoseconds since boot
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The first instant, when the timer started ticking
+    pub const BOOT: Instant = Instant(0);
+
+    /// The time of the last timer interrupt
+    pub fn now() -> Instant {
+        Instant(cycles_to_nanos(CYCLES.load(Ordering::Acquire)))
+    }
+
+    pub const fn from_nanos(nanos: u64) -> Instant {
+        Instant(nanos)
+    }
+
+    /// Nanoseconds since boot
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Time elapsed from `earlier` to `self`, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = duration.as_nanos();
+        if nanos > u64::MAX as u128 {
+            return None;
+        }
+        self.0.checked_add(nanos as u64).map(Instant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = duration.as_nanos();
+        if nanos > u64::MAX as u128 {
+            return None;
+        }
+        self.0.checked_sub(nanos as u64).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs).expect("overflow when adding a duration to an instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+    fn sub(self, rhs: Duration) -> Instant {
+        self.checked_sub(rhs).expect("overflow when subtracting a duration from an instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Instant({:?})", Duration::from_nanos(self.0))
+    }
+}
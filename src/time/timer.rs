@@ -0,0 +1,216 @@
+//! Sleeping tasks
+//!
+//! The deadlines of the sleeping tasks are kept in a min-heap. The timer
+//! interrupt only compares the time with the earliest deadline, kept in an
+//! atomic, and defers the expiry of the timers to the executor once it is
+//! reached: the wakers are woken, and dropped, outside of interrupt context.
+//!
+//! A `Sleep` dropped before its deadline, e.g. the one of a `timeout` whose
+//! future completed, removes its timer from the heap.
+
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use core::cmp::{Ordering as CmpOrdering, Reverse};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::{Duration, Instant};
+use crate::task::deferred;
+
+struct Timer {
+    deadline: Instant,
+    waker: Arc<AtomicWaker>,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Timer) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Timer) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Timer) -> CmpOrdering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<BinaryHeap<Reverse<Timer>>> = Mutex::new(BinaryHeap::new());
+}
+
+/// The earliest deadline in nanoseconds, `u64::MAX` if no timer is armed
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+/// `expire` was deferred and hasn't run yet
+static EXPIRY_QUEUED: AtomicBool = AtomicBool::new(false);
+
+/// Called by the timer interrupt handler once the time was updated
+pub(super) fn advance(now: Instant) {
+    if now.as_nanos() >= NEXT_DEADLINE.load(Ordering::Acquire)
+        && !EXPIRY_QUEUED.swap(true, Ordering::AcqRel)
+    {
+        // On overflow the next tick tries again
+        if !deferred::defer(expire, 0) {
+            EXPIRY_QUEUED.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Wakes the tasks whose deadline is reached, run by the executor
+fn expire(_: usize) {
+    EXPIRY_QUEUED.store(false, Ordering::Release);
+    let now = Instant::now();
+    let mut timers = TIMERS.lock();
+    while let Some(Reverse(timer)) = timers.peek() {
+        if timer.deadline > now {
+            break;
+        }
+        if let Some(Reverse(timer)) = timers.pop() {
+            timer.waker.wake();
+        }
+    }
+    update_next_deadline(&timers);
+}
+
+fn update_next_deadline(timers: &BinaryHeap<Reverse<Timer>>) {
+    let next = timers.peek().map_or(u64::MAX, |Reverse(timer)| timer.deadline.as_nanos());
+    NEXT_DEADLINE.store(next, Ordering::Release);
+}
+
+fn arm(deadline: Instant, waker: Arc<AtomicWaker>) {
+    let mut timers = TIMERS.lock();
+    timers.push(Reverse(Timer { deadline, waker }));
+    update_next_deadline(&timers);
+}
+
+/// Removes the timer of `waker` from the heap
+fn cancel(waker: &Arc<AtomicWaker>) {
+    let mut timers = TIMERS.lock();
+    let kept = core::mem::take(&mut *timers).into_vec().into_iter()
+        .filter(|Reverse(timer)| !Arc::ptr_eq(&timer.waker, waker))
+        .collect();
+    *timers = kept;
+    update_next_deadline(&timers);
+}
+
+/// Number of timers armed, expired ones included until the executor runs
+pub fn pending_timers() -> usize {
+    TIMERS.lock().len()
+}
+
+
+/// Waits until `duration` has elapsed
+///
+/// The resolution is the period of the timer, a sleep lasts at least
+/// `duration` and at most a period more. A deadline too far to be
+/// represented is never reached.
+pub fn sleep(duration: Duration) -> Sleep {
+    // The current tick started up to a period ago
+    let deadline = Instant::now().checked_add(duration)
+        .and_then(|deadline| deadline.checked_add(super::tick_period()))
+        .unwrap_or(Instant::from_nanos(u64::MAX));
+    sleep_until(deadline)
+}
+
+/// Waits until `deadline`
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, waker: None }
+}
+
+/// The future returned by `sleep` and `sleep_until`
+pub struct Sleep {
+    deadline: Instant,
+    /// Registered in the heap on the first poll, shared with the timer
+    waker: Option<Arc<AtomicWaker>>,
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            // The heap holds the other reference until the timer expires
+            if Arc::strong_count(&waker) > 1 {
+                cancel(&waker);
+            }
+        }
+    }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.is_elapsed() {
+            return Poll::Ready(());
+        }
+
+        match &self.waker {
+            Some(waker) => waker.register(cx.waker()),
+            None => {
+                let waker = Arc::new(AtomicWaker::new());
+                waker.register(cx.waker());
+                arm(self.deadline, waker.clone());
+                self.waker = Some(waker);
+            }
+        }
+
+        // The deadline may have passed before the timer was armed
+        if self.is_elapsed() { Poll::Ready(()) } else { Poll::Pending }
+    }
+}
+
+
+/// The future passed to `timeout` didn't complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs `future` for at most `duration`
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout { future, sleep: sleep(duration) }
+}
+
+/// The future returned by `timeout`
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Safety: `future` is never moved out of `self`, `sleep` is Unpin
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
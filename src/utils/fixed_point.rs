@@ -24,6 +24,8 @@ impl FixedPoint32_32 {
     pub fn new() -> Self { Self { val: 0 }}
     pub fn int(&self) -> u32 { (self.val >> 32) as u32}
     pub fn frac(&self) -> u32 { self.val as u32 }
+    /// The nearest integer, halves are rounded up
+    pub fn round(&self) -> u32 { self.int().saturating_add(self.frac() >> 31) }
 }   
 
 impl From<(u32,u32)> for FixedPoint32_32 {
@@ -99,6 +101,14 @@ pub fn addition_works(){
     assert_eq!(a+b, c);
 }
 
+#[test_case]
+pub fn round_works(){
+    assert_eq!(FixedPoint32_32::from((2, 0)).round(), 2);
+    assert_eq!(FixedPoint32_32::from((2, u32::MAX >> 1)).round(), 2);
+    assert_eq!(FixedPoint32_32::from((2, 1 << 31)).round(), 3);
+    assert_eq!((FixedPoint32_32::from((1_193_182, 0)) / 1000).round(), 1193);
+}
+
 #[test_case]
 pub fn fmt_works(){
     use crate::alloc::string::ToString;
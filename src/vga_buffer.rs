@@ -38,6 +38,20 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Prints unless the writer is locked, returns whether it printed
+///
+/// For the handlers which can interrupt the code holding the lock, even
+/// with interrupts disabled (NMI, machine check), where `println` would
+/// deadlock.
+pub fn try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    match WRITER.try_lock() {
+        Some(mut writer) => writer.write_fmt(args).is_ok(),
+        None => false,
+    }
+}
+
 
 // Buffer size 
 const BUFFER_HEIGHT : usize = 25;
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{PhysAddr, VirtAddr};
+use rost::acpi::{self, Signature};
+use rost::arch::apic::{ApicConfig, IoApic, LocalApic};
+use rost::interrupts::{self, Controller};
+use rost::memory::{self, BuddyFrameAllocator};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    interrupts::enable_apic();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+#[test_case]
+fn tables_are_found() {
+    let acpi = acpi::get().unwrap();
+    for signature in &[Signature::MADT, Signature::FADT, Signature::HPET] {
+        assert!(acpi.find_table(*signature).is_some(), "no {} table", signature);
+    }
+    assert!(acpi.find_table(Signature(*b"NONE")).is_none());
+}
+
+#[test_case]
+fn init_twice_gives_the_same_tables() {
+    let first = acpi::get().unwrap() as *const _;
+    assert_eq!(acpi::init().unwrap() as *const _, first);
+}
+
+#[test_case]
+fn madt_describes_the_apics() {
+    let madt = acpi::get().unwrap().madt.as_ref().unwrap();
+    assert_eq!(madt.local_apic, LocalApic::base_address());
+    assert!(madt.pic_compatible);
+
+    let local_apic = unsafe { LocalApic::new(madt.local_apic) };
+    assert!(madt.processors().any(|p| p.enabled && p.apic_id == local_apic.id()));
+
+    let io_apic = madt.io_apic_for(0).expect("no I/O APIC");
+    assert_eq!(io_apic.address, PhysAddr::new(0xfec0_0000));
+    assert_eq!(io_apic.id, unsafe { IoApic::new(io_apic.address) }.id());
+
+    // The PIT is on pin 2 on QEMU
+    assert!(madt.overrides().any(|o| o.irq == 0 && o.gsi == 2));
+}
+
+#[test_case]
+fn apic_uses_the_madt() {
+    let madt = acpi::get().unwrap().madt.as_ref().unwrap();
+    assert_eq!(interrupts::controller(), Controller::Apic);
+
+    let config = ApicConfig::detect();
+    assert_eq!(config.local_apic, madt.local_apic);
+    assert_eq!(config.isa_irqs[0].gsi, 2);
+    let io_apic = unsafe { IoApic::new(config.io_apic) };
+    assert_eq!(io_apic.redirection(2 - config.io_apic_gsi_base).vector, 32);
+}
+
+#[test_case]
+fn fadt_is_parsed() {
+    let fadt = acpi::get().unwrap().fadt.unwrap();
+    // QEMU's PIIX4 power management
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_ne!(fadt.pm1a_control_block, 0);
+    assert_ne!(fadt.pm_timer_block, 0);
+}
+
+#[test_case]
+fn hpet_is_parsed() {
+    let hpet = acpi::get().unwrap().hpet.unwrap();
+    assert_eq!(hpet.address, PhysAddr::new(0xfed0_0000));
+    assert!(hpet.comparators >= 3);
+}
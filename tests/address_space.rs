@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PageTableFlags as Flags};
+use rost::memory::{self, AddressSpace, BuddyFrameAllocator};
+use rost::memory::address_space::USER_SPACE_START;
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    rost::allocator::init_heap().expect("heap alloc failed");
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn user_pages(count: u64) -> impl Iterator<Item = Page> + Clone {
+    let start = Page::containing_address(VirtAddr::new(USER_SPACE_START));
+    Page::range(start, start + count)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames())
+}
+
+#[test_case]
+fn kernel_is_mapped() {
+    use alloc::boxed::Box;
+
+    let mut space = AddressSpace::new().unwrap();
+    unsafe { space.switch() };
+    assert!(space.is_active());
+
+    // Code, stack, heap and VGA buffer are all still there
+    let b = Box::new(42);
+    rost::println!("hello from a new address space");
+    assert_eq!(*b, 42);
+
+    unsafe { AddressSpace::switch_to_kernel() };
+}
+
+#[test_case]
+fn spaces_are_isolated() {
+    let mut a = AddressSpace::new().unwrap();
+    let mut b = AddressSpace::new().unwrap();
+    a.map_user(user_pages(4), Flags::WRITABLE).unwrap();
+    b.map_user(user_pages(4), Flags::WRITABLE).unwrap();
+
+    let ptr = VirtAddr::new(USER_SPACE_START).as_mut_ptr::<u64>();
+    unsafe {
+        a.switch();
+        assert_eq!(*ptr, 0, "user pages must be zeroed");
+        *ptr = 1;
+        b.switch();
+        *ptr = 2;
+        a.switch();
+        assert_eq!(*ptr, 1);
+        AddressSpace::switch_to_kernel();
+    }
+
+    // Not visible in the kernel's own page table
+    let kernel = memory::with_kernel_memory(|mem| mem.mapper.translate(VirtAddr::new(USER_SPACE_START)));
+    assert_eq!(kernel, None);
+}
+
+#[test_case]
+fn unmap_user() {
+    let mut space = AddressSpace::new().unwrap();
+    space.map_user(user_pages(2), Flags::WRITABLE).unwrap();
+    space.unmap_user(user_pages(1)).unwrap();
+
+    assert!(space.translate(VirtAddr::new(USER_SPACE_START)).is_none());
+    assert!(space.translate(VirtAddr::new(USER_SPACE_START + 4096)).is_some());
+}
+
+#[test_case]
+fn teardown_frees_everything() {
+    let free = free_frames();
+    {
+        let mut space = AddressSpace::new().unwrap();
+        space.map_user(user_pages(600), Flags::WRITABLE).unwrap();
+        unsafe { space.switch() };
+        // Dropped while active
+    }
+    // Dropping the active space switches back to the kernel's
+    assert_eq!(memory::with_kernel_memory(|mem| mem.mapper.level_4_frame()), Cr3::read().0);
+    assert_eq!(free_frames(), free);
+}
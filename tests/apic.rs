@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use rost::arch::apic::{self, ApicConfig, IoApic, LocalApic};
+use rost::arch::port::Port;
+use rost::interrupts::{self, Controller};
+use rost::memory::{self, BuddyFrameAllocator};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    interrupts::enable_apic();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+#[test_case]
+fn apic_replaces_pics() {
+    assert!(apic::is_supported());
+    assert_eq!(interrupts::controller(), Controller::Apic);
+
+    let masks = unsafe { (Port::<u8>::new(0x21).read(), Port::<u8>::new(0xa1).read()) };
+    assert_eq!(masks, (0xff, 0xff));
+}
+
+#[test_case]
+fn local_apic_is_integrated() {
+    let local_apic = unsafe { LocalApic::new(LocalApic::base_address()) };
+    // 0x1X for integrated APICs, 0x0X for the discrete 82489DX
+    assert!(local_apic.version() >= 0x10, "version {:#x}", local_apic.version());
+}
+
+#[test_case]
+fn isa_irqs_are_routed() {
+    let config = ApicConfig::detect();
+    let io_apic = unsafe { IoApic::new(config.io_apic) };
+    assert!(io_apic.pins() >= 16);
+
+    let timer = io_apic.redirection(config.isa_irqs[0].gsi);
+    assert_eq!(timer.vector, 32);
+    assert!(!timer.masked);
+
+    let keyboard = io_apic.redirection(config.isa_irqs[1].gsi);
+    assert_eq!(keyboard.vector, 33);
+    assert!(!keyboard.masked);
+}
+
+#[test_case]
+fn timer_keeps_firing() {
+    // Without end of interrupts, the timer would stop after the first one
+    for _ in 0..10 {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,171 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Page, PageTableFlags as Flags, PhysFrame};
+use rost::memory::{self, AddressSpace, BuddyFrameAllocator};
+use rost::memory::address_space::USER_SPACE_START;
+use rost::memory::cow::COW;
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+const PAGES: u64 = 4;
+
+fn user_pages() -> impl Iterator<Item = Page> {
+    let start = Page::containing_address(VirtAddr::new(USER_SPACE_START));
+    Page::range(start, start + PAGES)
+}
+
+fn word(page: u64) -> *mut u64 {
+    VirtAddr::new(USER_SPACE_START + page * 4096).as_mut_ptr()
+}
+
+fn frame_of(space: &AddressSpace, page: u64) -> PhysFrame {
+    let translation = space.translate(VirtAddr::new(USER_SPACE_START + page * 4096)).unwrap();
+    PhysFrame::containing_address(translation.frame_start)
+}
+
+fn ref_count(frame: PhysFrame) -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.ref_count(frame))
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames())
+}
+
+/// An address space with `i` written at the start of its page `i`
+fn filled_space() -> AddressSpace {
+    let mut space = AddressSpace::new().unwrap();
+    space.map_user(user_pages(), Flags::WRITABLE).unwrap();
+    unsafe {
+        space.switch();
+        for i in 0..PAGES {
+            *word(i) = i;
+        }
+        AddressSpace::switch_to_kernel();
+    }
+    space
+}
+
+#[test_case]
+fn frames_are_reference_counted() {
+    memory::with_kernel_memory(|mem| unsafe {
+        let allocator = &mut mem.frame_allocator;
+        let free = allocator.free_frames();
+        let frame = allocator.allocate(0).unwrap();
+        assert_eq!(allocator.ref_count(frame), 1);
+
+        allocator.share(frame);
+        assert_eq!(allocator.ref_count(frame), 2);
+        allocator.free(frame, 0);
+        assert_eq!(allocator.ref_count(frame), 1);
+        assert_eq!(allocator.free_frames(), free - 1);
+        allocator.free(frame, 0);
+        assert_eq!(allocator.free_frames(), free);
+    });
+}
+
+#[test_case]
+fn clone_shares_frames() {
+    let mut parent = filled_space();
+    let child = parent.clone_cow().unwrap();
+
+    for i in 0..PAGES {
+        let frame = frame_of(&parent, i);
+        assert_eq!(frame_of(&child, i), frame);
+        assert_eq!(ref_count(frame), 2);
+
+        for space in [&parent, &child].iter() {
+            let flags = space.translate(VirtAddr::new(USER_SPACE_START + i * 4096)).unwrap().flags;
+            assert!(flags.contains(COW));
+            assert!(!flags.contains(Flags::WRITABLE));
+        }
+    }
+}
+
+#[test_case]
+fn write_fault_copies_the_page() {
+    let mut parent = filled_space();
+    let mut child = parent.clone_cow().unwrap();
+    let shared = frame_of(&parent, 0);
+
+    unsafe {
+        child.switch();
+        assert_eq!(*word(0), 0);
+        *word(0) = 42;
+        assert_eq!(*word(0), 42);
+        // Untouched pages are still shared
+        assert_eq!(*word(1), 1);
+
+        parent.switch();
+        assert_eq!(*word(0), 0);
+        AddressSpace::switch_to_kernel();
+    }
+
+    assert_ne!(frame_of(&child, 0), shared);
+    assert_eq!(frame_of(&parent, 0), shared);
+    assert_eq!(ref_count(shared), 1);
+    assert_eq!(frame_of(&child, 1), frame_of(&parent, 1));
+}
+
+#[test_case]
+fn last_reference_is_reused() {
+    let mut parent = filled_space();
+    let mut child = parent.clone_cow().unwrap();
+    let shared = frame_of(&parent, 2);
+
+    unsafe {
+        child.switch();
+        *word(2) = 7;
+        // The parent is the only one left referencing the frame, no copy needed
+        parent.switch();
+        *word(2) = 8;
+        AddressSpace::switch_to_kernel();
+    }
+
+    assert_eq!(frame_of(&parent, 2), shared);
+    let flags = parent.translate(VirtAddr::new(USER_SPACE_START + 2 * 4096)).unwrap().flags;
+    assert!(flags.contains(Flags::WRITABLE));
+    assert!(!flags.contains(COW));
+}
+
+#[test_case]
+fn teardown_frees_shared_frames() {
+    let free = free_frames();
+    {
+        let mut parent = filled_space();
+        let mut child = parent.clone_cow().unwrap();
+        let _grandchild = child.clone_cow().unwrap();
+        unsafe {
+            child.switch();
+            *word(3) = 3;
+            AddressSpace::switch_to_kernel();
+        }
+    }
+    assert_eq!(free_frames(), free);
+}
@@ -0,0 +1,149 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(asm)]
+
+extern crate alloc;
+
+use bootloader::BootInfo;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rost::interrupts::{register_irq, unregister_irq};
+use rost::task::deferred::{self, IrqEvent, WORK_QUEUE_LEN};
+use rost::task::{Task, simple_executor::SimpleExecutor};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    deferred::init();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Nothing is wired to IRQ 5 on QEMU, the tests raise it with `int 37`
+const LINE: u8 = 5;
+
+static BOTTOM_HALVES: AtomicUsize = AtomicUsize::new(0);
+static EVENT: IrqEvent = IrqEvent::new();
+
+fn bottom_half(arg: usize) {
+    assert_eq!(arg, 42);
+    BOTTOM_HALVES.fetch_add(1, Ordering::SeqCst);
+}
+
+fn defer_from_irq(_line: u8) {
+    assert!(deferred::defer(bottom_half, 42));
+}
+
+fn signal_from_irq(_line: u8) {
+    EVENT.signal();
+}
+
+fn raise_irq5() {
+    unsafe { asm!("int 37") };
+}
+
+#[test_case]
+fn work_is_deferred_out_of_the_handler() {
+    register_irq(LINE, defer_from_irq).unwrap();
+    let before = BOTTOM_HALVES.load(Ordering::SeqCst);
+    raise_irq5();
+    raise_irq5();
+    unregister_irq(LINE, defer_from_irq).unwrap();
+
+    assert!(deferred::has_pending());
+    assert_eq!(BOTTOM_HALVES.load(Ordering::SeqCst), before);
+    assert_eq!(deferred::run_pending(), 2);
+    assert_eq!(BOTTOM_HALVES.load(Ordering::SeqCst), before + 2);
+    assert!(!deferred::has_pending());
+}
+
+#[test_case]
+fn full_queue_counts_overflows() {
+    let before = deferred::stats();
+    for _ in 0..WORK_QUEUE_LEN {
+        assert!(deferred::defer(bottom_half, 42));
+    }
+    assert!(!deferred::defer(bottom_half, 42));
+
+    let stats = deferred::stats();
+    assert_eq!(stats.overflows, before.overflows + 1);
+    assert_eq!(stats.deferred, before.deferred + WORK_QUEUE_LEN as u64);
+    assert_eq!(deferred::run_pending(), WORK_QUEUE_LEN);
+    assert_eq!(deferred::stats().completed, before.completed + WORK_QUEUE_LEN as u64);
+}
+
+#[test_case]
+fn work_deferred_by_work_waits_for_the_next_run() {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn again(times: usize) {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+        if times > 1 {
+            assert!(deferred::defer(again, times - 1));
+        }
+    }
+
+    assert!(deferred::defer(again, 3));
+    assert_eq!(deferred::run_pending(), 1);
+    assert_eq!(deferred::run_pending(), 1);
+    assert_eq!(deferred::run_pending(), 1);
+    assert_eq!(deferred::run_pending(), 0);
+    assert_eq!(RUNS.load(Ordering::SeqCst), 3);
+}
+
+#[test_case]
+fn signals_are_coalesced() {
+    register_irq(LINE, signal_from_irq).unwrap();
+    raise_irq5();
+    raise_irq5();
+    raise_irq5();
+    unregister_irq(LINE, signal_from_irq).unwrap();
+
+    assert_eq!(EVENT.take(), 3);
+    assert_eq!(EVENT.take(), 0);
+}
+
+#[test_case]
+fn tasks_wait_for_events() {
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+    async fn waiter() {
+        let signals = EVENT.wait().await;
+        WOKEN.store(signals, Ordering::SeqCst);
+    }
+
+    async fn raiser() {
+        register_irq(LINE, signal_from_irq).unwrap();
+        raise_irq5();
+        unregister_irq(LINE, signal_from_irq).unwrap();
+    }
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(waiter()));
+    executor.spawn(Task::new(raiser()));
+    executor.run();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags as Flags;
+use rost::memory::{self, BuddyFrameAllocator};
+use rost::memory::demand::{self, Backing, Region, RegionError};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Every test gets its own region
+fn region_start(test: u64) -> VirtAddr {
+    VirtAddr::new(0x_7000_0000_0000 + (test << 30))
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::with_kernel_memory(|mem| mem.mapper.translate(addr).is_some())
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames())
+}
+
+#[test_case]
+fn pages_are_mapped_on_access() {
+    let start = region_start(0);
+    demand::register(Region::anonymous(start, 16 * 4096, Flags::WRITABLE)).unwrap();
+    let faults = demand::resolved_faults();
+
+    assert!(!is_mapped(start));
+    let ptr = start.as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    assert!(is_mapped(start));
+    assert!(!is_mapped(start + 4096u64));
+    assert_eq!(demand::resolved_faults(), faults + 1);
+
+    unsafe { demand::unregister(start) }.unwrap();
+}
+
+#[test_case]
+fn every_page_is_zeroed() {
+    let start = region_start(1);
+    let pages = 64;
+    demand::register(Region::anonymous(start, pages * 4096, Flags::WRITABLE)).unwrap();
+
+    for i in 0..pages {
+        let ptr = (start + i * 4096 + 8).as_mut_ptr::<u64>();
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(i);
+        }
+    }
+    for i in 0..pages {
+        let ptr = (start + i * 4096 + 8).as_ptr::<u64>();
+        assert_eq!(unsafe { ptr.read_volatile() }, i);
+    }
+
+    unsafe { demand::unregister(start) }.unwrap();
+}
+
+struct Pattern;
+
+impl Backing for Pattern {
+    fn fill(&self, offset: u64, page: &mut [u8]) {
+        for byte in page.iter_mut() {
+            *byte = (offset / 4096) as u8;
+        }
+    }
+}
+
+#[test_case]
+fn custom_backing() {
+    let start = region_start(2);
+    demand::register(Region::new(start, 4 * 4096, Flags::empty(), &Pattern)).unwrap();
+
+    for i in 0..4u64 {
+        let ptr = (start + i * 4096 + 100).as_ptr::<u8>();
+        assert_eq!(unsafe { ptr.read_volatile() }, i as u8);
+    }
+
+    unsafe { demand::unregister(start) }.unwrap();
+}
+
+#[test_case]
+fn unregister_frees_frames() {
+    let start = region_start(3);
+    let free = free_frames();
+    demand::register(Region::anonymous(start, 32 * 4096, Flags::WRITABLE)).unwrap();
+    for i in 0..32u64 {
+        unsafe { (start + i * 4096).as_mut_ptr::<u8>().write_volatile(1) };
+    }
+    assert!(free_frames() < free);
+
+    let region = unsafe { demand::unregister(start) }.unwrap();
+    assert_eq!(region.start(), start);
+    assert!(!is_mapped(start));
+    assert!(demand::region_at(start).is_none());
+    // The L1 and L2 tables are freed too (the L3 one was created by the first test)
+    assert_eq!(free_frames(), free);
+}
+
+#[test_case]
+fn invalid_regions() {
+    let start = region_start(4);
+    assert_eq!(demand::register(Region::anonymous(start + 1u64, 4096, Flags::WRITABLE)),
+        Err(RegionError::Unaligned));
+    assert_eq!(demand::register(Region::anonymous(start, 0, Flags::WRITABLE)),
+        Err(RegionError::Unaligned));
+
+    demand::register(Region::anonymous(start, 4 * 4096, Flags::WRITABLE)).unwrap();
+    assert_eq!(demand::register(Region::anonymous(start + 3 * 4096u64, 4096, Flags::WRITABLE)),
+        Err(RegionError::Overlapping));
+    unsafe { demand::unregister(start) }.unwrap();
+}
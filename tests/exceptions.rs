@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(asm)]
+
+use core::panic::PanicInfo;
+use rost::interrupts::exceptions::{self, DescriptorTable};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rost::init();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+// The faulting instructions are 2 bytes long, the handlers resume after them
+
+#[test_case]
+fn invalid_opcode_is_reported() {
+    exceptions::take_last_report();
+    exceptions::recover_next(2);
+    unsafe { asm!("ud2") };
+
+    let report = exceptions::take_last_report().expect("no #UD");
+    assert_eq!(report.vector, 6);
+    assert_eq!(report.mnemonic(), "#UD");
+    assert_eq!(report.error_code, None);
+}
+
+#[test_case]
+fn divide_error_is_reported() {
+    exceptions::take_last_report();
+    exceptions::recover_next(2);
+    unsafe {
+        asm!("div ecx", in("ecx") 0u32, inout("eax") 1u32 => _, inout("edx") 0u32 => _);
+    }
+
+    let report = exceptions::take_last_report().expect("no #DE");
+    assert_eq!(report.vector, 0);
+    assert_eq!(report.name(), "divide error");
+}
+
+#[test_case]
+fn general_protection_fault_is_reported() {
+    exceptions::take_last_report();
+    exceptions::recover_next(2);
+    // Way past the end of the GDT
+    unsafe { asm!("mov ds, eax", in("eax") 0x1230u32) };
+
+    let report = exceptions::take_last_report().expect("no #GP");
+    assert_eq!(report.vector, 13);
+    assert_eq!(report.error_code, Some(0x1230));
+    let selector = report.selector().expect("no selector");
+    assert_eq!(selector.table(), DescriptorTable::Gdt);
+    assert_eq!(selector.index(), 0x246);
+    assert!(!selector.external());
+}
+
+#[test_case]
+fn faults_report_where_they_happened() {
+    exceptions::recover_next(2);
+    let ip: u64;
+    unsafe { asm!("lea {}, [rip]", "ud2", out(reg) ip) };
+
+    // `rip` is the address of the next instruction, the `ud2`
+    let report = exceptions::take_last_report().expect("no #UD");
+    assert_eq!(report.instruction_pointer.as_u64(), ip);
+}
+
+#[test_case]
+fn breakpoints_are_resumed() {
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(exceptions::take_last_report().map(|r| r.vector), Some(3));
+}
+
+#[test_case]
+fn nmi_doesnt_wait_for_the_vga_writer() {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    // Like an NMI arriving while something prints
+    without_interrupts(|| {
+        let _writer = rost::vga_buffer::WRITER.lock();
+        unsafe { asm!("int 2") };
+    });
+    assert_eq!(exceptions::take_last_report().map(|r| r.vector), Some(2));
+}
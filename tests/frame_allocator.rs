@@ -0,0 +1,161 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use rost::memory::BuddyFrameAllocator;
+use rost::memory::buddy::{Zone, MAX_ORDER};
+use spin::Mutex;
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn with_allocator(f: impl FnOnce(&mut BuddyFrameAllocator)) {
+    f(FRAME_ALLOCATOR.lock().as_mut().unwrap());
+}
+
+#[test_case]
+fn counters_are_consistent() {
+    with_allocator(|fa| {
+        assert!(fa.total_frames() > 0);
+        assert_eq!(fa.used_frames() + fa.free_frames(), fa.total_frames());
+
+        let free = fa.free_frames();
+        let frame = fa.allocate(0).unwrap();
+        assert_eq!(fa.free_frames(), free - 1);
+        unsafe { fa.free(frame, 0) };
+        assert_eq!(fa.free_frames(), free);
+    });
+}
+
+#[test_case]
+fn blocks_are_aligned() {
+    with_allocator(|fa| {
+        for order in 0..=MAX_ORDER {
+            let frame = fa.allocate(order).unwrap();
+            let size = 4096u64 << order;
+            assert_eq!(frame.start_address().as_u64() % size, 0);
+            unsafe { fa.free(frame, order) };
+        }
+    });
+}
+
+#[test_case]
+fn buddies_merge_back() {
+    with_allocator(|fa| {
+        let blocks = fa.free_blocks(MAX_ORDER);
+
+        // Splitting big blocks in 4KiB frames then freeing them all
+        // must give the big blocks back
+        let mut frames = [None; 1024];
+        for slot in frames.iter_mut() {
+            *slot = fa.allocate(0);
+        }
+        assert!(fa.free_blocks(MAX_ORDER) < blocks);
+        for frame in frames.iter().rev() {
+            unsafe { fa.free(frame.unwrap(), 0) };
+        }
+        assert_eq!(fa.free_blocks(MAX_ORDER), blocks);
+    });
+}
+
+/// The old allocator could never give a frame back
+#[test_case]
+fn frames_are_reused() {
+    with_allocator(|fa| {
+        for _ in 0..fa.total_frames() * 2 {
+            let frame = fa.allocate(0).expect("out of frames");
+            unsafe { fa.free(frame, 0) };
+        }
+    });
+}
+
+#[test_case]
+fn zones_are_respected() {
+    with_allocator(|fa| {
+        for &zone in Zone::ALL.iter() {
+            let free = fa.free_frames_in(zone);
+            let frame = match fa.allocate_frame_in(zone) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            assert!(frame.start_address().as_u64() < zone.limit());
+            // Taken from the zone itself as long as it has free frames
+            if free > 0 {
+                assert_eq!(Zone::of(frame.start_address()), zone);
+                assert_eq!(fa.free_frames_in(zone), free - 1);
+            }
+            unsafe { fa.free(frame, 0) };
+        }
+        assert_eq!(Zone::ALL.iter().map(|&z| fa.free_frames_in(z)).sum::<usize>(), fa.free_frames());
+    });
+}
+
+/// The DMA zone is only used when nothing else is left
+#[test_case]
+fn low_memory_is_kept() {
+    with_allocator(|fa| {
+        if fa.free_frames_in(Zone::Dma32) + fa.free_frames_in(Zone::Normal) == 0 {
+            return;
+        }
+        let frame = fa.allocate(0).unwrap();
+        assert_ne!(Zone::of(frame.start_address()), Zone::Dma);
+        unsafe { fa.free(frame, 0) };
+    });
+}
+
+#[test_case]
+fn contiguous_allocation() {
+    with_allocator(|fa| {
+        let free = fa.free_frames();
+        let frame = fa.allocate_contiguous(3, 64 * 1024, Zone::Dma).unwrap();
+        assert_eq!(frame.start_address().as_u64() % (64 * 1024), 0);
+        assert!(frame.start_address().as_u64() + 3 * 4096 <= Zone::Dma.limit());
+        // The rest of the block is given back
+        assert_eq!(fa.free_frames(), free - 3);
+
+        unsafe { fa.free_contiguous(frame, 3) };
+        assert_eq!(fa.free_frames(), free);
+    });
+}
+
+/// More frames than the biggest block
+#[test_case]
+fn large_contiguous_allocation() {
+    with_allocator(|fa| {
+        let (free, blocks) = (fa.free_frames(), fa.free_blocks(MAX_ORDER));
+        let count = 1000;
+        let frame = fa.allocate_contiguous(count, 2 * 1024 * 1024, Zone::Dma32).unwrap();
+        assert_eq!(frame.start_address().as_u64() % (2 * 1024 * 1024), 0);
+        assert_eq!(fa.free_frames(), free - count);
+
+        unsafe { fa.free_contiguous(frame, count) };
+        assert_eq!(fa.free_frames(), free);
+        assert_eq!(fa.free_blocks(MAX_ORDER), blocks);
+    });
+}
@@ -14,18 +14,18 @@ use core::panic::PanicInfo;
 #[no_mangle]
 pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     use rost::allocator;
-    use rost::memory::{self, BootInfoFrameAllocator};
+    use rost::memory::{self, BuddyFrameAllocator};
     use x86_64::VirtAddr;
 
     rost::init();
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper= unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
-            .expect("heap alloc failed");
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
     test_main();
 
     panic!("Execution continued after stack overflow");
@@ -67,3 +67,68 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+
+#[test_case]
+fn heap_grows() {
+    use rost::allocator::{heap_size, HEAP_SIZE};
+
+    let n = 4 * HEAP_SIZE;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i as u8);
+    }
+    assert!(heap_size() > HEAP_SIZE);
+    assert_eq!(vec[n - 1], (n - 1) as u8);
+}
+
+
+#[test_case]
+fn heap_stops_at_max_size() {
+    use alloc::alloc::{alloc, Layout};
+    use rost::allocator::{heap_size, set_max_size, HEAP_MAX_SIZE};
+
+    set_max_size(heap_size());
+    let layout = Layout::from_size_align(heap_size() + 1, 8).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    set_max_size(HEAP_MAX_SIZE);
+
+    assert!(ptr.is_null());
+}
+
+
+#[test_case]
+fn stats_track_allocations() {
+    use rost::allocator::stats;
+
+    let before = stats();
+    let x = Box::new([0u8; 100]);
+    let during = stats();
+    assert_eq!(during.live_allocations, before.live_allocations + 1);
+    assert_eq!(during.allocated_bytes, before.allocated_bytes + 100);
+    assert!(during.peak_bytes >= during.allocated_bytes);
+    assert_eq!(during.histogram[4], before.histogram[4] + 1); // 65..=128 bytes
+    drop(x);
+    assert_eq!(stats().live_allocations, before.live_allocations);
+    assert_eq!(stats().allocated_bytes, before.allocated_bytes);
+}
+
+
+#[test_case]
+fn no_leaks_after_task() {
+    use rost::allocator::stats;
+    use rost::task::{Task, simple_executor::SimpleExecutor};
+
+    async fn work() {
+        let v: Vec<u32> = (0..1000).collect();
+        assert_eq!(v.len(), 1000);
+    }
+
+    let live = stats().live_allocations;
+    {
+        let mut executor = SimpleExecutor::new();
+        executor.spawn(Task::new(work()));
+        executor.run();
+    }
+    assert_eq!(stats().live_allocations, live);
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+
+
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use rost::allocator::{leak_mark, leaks_since, report_leaks_since};
+use rost::task::{Task, simple_executor::SimpleExecutor};
+
+#[test_case]
+fn task_does_not_leak() {
+    async fn work() {
+        let v: Vec<u32> = (0..1000).collect();
+        let b = Box::new(v.len());
+        assert_eq!(*b, 1000);
+    }
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(work()));
+
+    let mark = leak_mark();
+    executor.run();
+    report_leaks_since(mark);
+    assert_eq!(leaks_since(mark), 0);
+}
+
+#[test_case]
+fn leaks_are_found() {
+    let mark = leak_mark();
+    let leaked = Box::leak(Box::new(42u64));
+    assert_eq!(*leaked, 42);
+    assert_eq!(leaks_since(mark), 1);
+
+    unsafe { drop(Box::from_raw(leaked)) };
+    assert_eq!(leaks_since(mark), 0);
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use rost::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use rost::memory::{self, BuddyFrameAllocator};
+use bootloader::BootInfo;
+use x86_64::VirtAddr;
+
+/// What the sanitizer must panic with
+const EXPECTED: &str = "red zone after";
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_overflow::heap_overflow...\t");
+
+    rost::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    rost::allocator::init_heap().expect("heap alloc failed");
+
+    // One byte past the end of the allocation
+    let mut b = Box::new([0u8; 32]);
+    unsafe { *b.as_mut_ptr().add(32) = 0 };
+    drop(b);
+
+    serial_println!("[failed]\n");
+    serial_println!("Error: the overflow wasn't detected");
+    exit_qemu(QemuExitCode::Failed);
+    rost::hlt_loop();
+}
+
+/// Keeps the beginning of the panic message
+struct Message {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message { buf: [0; 256], len: 0 };
+    let _ = write!(message, "{}", info);
+    let message = &message.buf[..message.len];
+
+    if message.windows(EXPECTED.len()).any(|w| w == EXPECTED.as_bytes()) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        rost::hlt_loop();
+    }
+    rost::test_panic_handler(info)
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+
+
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use rost::allocator::{self, sanitizer::{FREED_BYTE, QUARANTINE_BYTES, REDZONE_BYTE, REDZONE_SIZE}};
+
+#[test_case]
+fn allocations_work() {
+    let mut v = Vec::new();
+    for i in 0..1000u64 {
+        v.push(i);
+    }
+    assert_eq!(v.iter().sum::<u64>(), 999 * 1000 / 2);
+
+    let aligned = Box::new(Aligned([7; 64]));
+    assert_eq!(&*aligned as *const Aligned as usize % 256, 0);
+    assert!(aligned.0.iter().all(|&b| b == 7));
+}
+
+#[repr(align(256))]
+struct Aligned([u8; 64]);
+
+#[test_case]
+fn allocations_are_surrounded_by_red_zones() {
+    let b = Box::new([0u8; 24]);
+    let ptr = b.as_ptr();
+    unsafe {
+        for i in 1..=REDZONE_SIZE {
+            assert_eq!(*ptr.sub(i), REDZONE_BYTE);
+            assert_eq!(*ptr.add(24 + i - 1), REDZONE_BYTE);
+        }
+    }
+}
+
+#[test_case]
+fn freed_memory_is_poisoned() {
+    let b = Box::new([0x11u8; 32]);
+    let ptr = b.as_ptr();
+    drop(b);
+    // Still mapped, and in quarantine
+    let content = unsafe { core::slice::from_raw_parts(ptr, 32) };
+    assert!(content.iter().all(|&b| b == FREED_BYTE));
+}
+
+#[test_case]
+fn freed_blocks_are_not_reused_right_away() {
+    let first = Box::new(1u64);
+    let ptr = &*first as *const u64;
+    drop(first);
+    let second = Box::new(2u64);
+    assert_ne!(&*second as *const u64, ptr);
+}
+
+#[test_case]
+fn quarantine_is_bounded() {
+    let before = allocator::heap_size();
+    // 2MiB freed in total
+    for i in 0..2000 {
+        drop(Box::new([i as u8; 1024]));
+    }
+    assert!(allocator::heap_size() - before < 2 * QUARANTINE_BYTES);
+    allocator::flush_quarantine();
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::PageTableFlags;
+use rost::memory::{self, BuddyFrameAllocator};
+
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+fn phys_mem_offset() -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed))
+}
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    PHYS_MEM_OFFSET.store(boot_info.physical_memory_offset, Ordering::Relaxed);
+    let mapper = unsafe { memory::init(phys_mem_offset()) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset())
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// The bootloader maps the physical memory with huge pages
+#[test_case]
+fn translate_physical_memory_mapping() {
+    for &phys in [0u64, 0x1234, 0xb8000, 0x20_1234].iter() {
+        let virt = phys_mem_offset() + phys;
+        let translated = unsafe { memory::translate_addr(virt, phys_mem_offset()) };
+        assert_eq!(translated, Some(PhysAddr::new(phys)));
+    }
+}
+
+/// Flags of the L2 entry covering `virt`, found by walking the active tables
+fn l2_entry_flags(virt: VirtAddr) -> PageTableFlags {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::PageTable;
+
+    let table = |phys: PhysAddr| -> &'static PageTable {
+        unsafe { &*(phys_mem_offset() + phys.as_u64()).as_ptr() }
+    };
+    let l4 = table(Cr3::read().0.start_address());
+    let l3 = table(l4[virt.p4_index()].addr());
+    let l2 = table(l3[virt.p3_index()].addr());
+    l2[virt.p2_index()].flags()
+}
+
+#[test_case]
+fn map_2mib_region() {
+    use rost::memory::buddy::MAX_ORDER;
+
+    let frame = memory::with_kernel_memory(|mem| mem.frame_allocator.allocate(MAX_ORDER))
+        .expect("no 2MiB block left");
+    let phys = frame.start_address();
+    let virt = VirtAddr::new(0x_7777_0000_0000);
+    let size = 2 * 1024 * 1024;
+
+    memory::with_kernel_memory(|mem| unsafe {
+        memory::map_huge_region(mem, virt, phys, size,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE)
+    }).expect("map_huge_region failed");
+    assert!(l2_entry_flags(virt).contains(PageTableFlags::HUGE_PAGE),
+        "the region isn't mapped with a 2MiB page");
+
+    let last = virt + (size - 8);
+    unsafe { *last.as_mut_ptr::<u64>() = 0xdead_beef };
+    let translated = unsafe { memory::translate_addr(last, phys_mem_offset()) };
+    assert_eq!(translated, Some(phys + (size - 8)));
+
+    // The same memory seen through the physical memory mapping
+    let alias = phys_mem_offset() + (phys.as_u64() + size - 8);
+    assert_eq!(unsafe { *alias.as_ptr::<u64>() }, 0xdead_beef);
+}
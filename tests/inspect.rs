@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Page, PageTableFlags as Flags};
+use rost::memory::{self, BuddyFrameAllocator, KernelMemory};
+use rost::memory::buddy::Zone;
+use rost::memory::inspect::{self, MappedRange};
+
+static MEMORY_MAP: Mutex<Option<&'static MemoryMap>> = Mutex::new(None);
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    *MEMORY_MAP.lock() = Some(&boot_info.memory_map);
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn memory_map() -> &'static MemoryMap {
+    MEMORY_MAP.lock().unwrap()
+}
+
+/// The ranges starting between `start` and `end` (16 at most), and their number
+fn ranges_in(start: u64, end: u64) -> ([Option<MappedRange>; 16], usize) {
+    let mut ranges = [None; 16];
+    let mut count = 0;
+    memory::with_kernel_memory(|mem| inspect::for_each_range(&mem.mapper, |range| {
+        let virt = range.virt.as_u64();
+        if virt >= start && virt < end && count < ranges.len() {
+            ranges[count] = Some(*range);
+            count += 1;
+        }
+    }));
+    (ranges, count)
+}
+
+#[test_case]
+fn ranges_are_coalesced() {
+    let start = 0x_6000_0000_0000u64;
+    let first = Page::containing_address(VirtAddr::new(start));
+    let rw = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| unsafe {
+        let frame = frame_allocator.allocate_contiguous(8, 4096, Zone::Normal).unwrap();
+        for i in 0..8 {
+            // The page in the middle is read-only
+            let flags = if i == 4 { Flags::PRESENT | Flags::NO_EXECUTE } else { rw };
+            mapper.map(first + i, frame + i, flags, frame_allocator).unwrap().flush();
+        }
+    });
+
+    let (ranges, count) = ranges_in(start, start + (1 << 30));
+    let range = |i: usize| ranges[i].unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(range(0).virt.as_u64(), start);
+    assert_eq!(range(0).size, 4 * 4096);
+    assert_eq!(range(1).size, 4096);
+    assert_eq!(range(2).size, 3 * 4096);
+    assert_eq!(range(1).phys, range(0).phys_end());
+
+    assert!(range(0).access.writable && range(0).access.no_execute);
+    assert!(!range(1).access.writable);
+    assert!(!range(0).access.user && !range(0).access.huge);
+}
+
+/// The bootloader maps the physical memory with huge pages
+#[test_case]
+fn physical_memory_mapping() {
+    let offset = memory::physical_memory_offset().as_u64();
+    let (ranges, count) = ranges_in(offset, offset + 1);
+    assert_eq!(count, 1);
+    let range = ranges[0].unwrap();
+    assert_eq!(range.phys.as_u64(), 0);
+    assert!(range.access.huge);
+    assert!(range.size >= inspect::memory_totals(memory_map()).usable);
+}
+
+#[test_case]
+fn access_formatting() {
+    use core::fmt::Write;
+
+    struct Buffer { bytes: [u8; 16], len: usize }
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    let access = inspect::Access { writable: true, user: false, no_execute: true, huge: false, global: true };
+    let mut buffer = Buffer { bytes: [0; 16], len: 0 };
+    write!(buffer, "{}", access).unwrap();
+    assert_eq!(&buffer.bytes[..buffer.len], b"W-NX-G");
+}
+
+#[test_case]
+fn memory_map_regions() {
+    let map = memory_map();
+    let mut previous: Option<inspect::Region> = None;
+    for region in inspect::memory_regions(map) {
+        assert!(region.start < region.end);
+        if let Some(previous) = previous {
+            assert!(previous.end <= region.start);
+            // Adjacent regions of the same type are merged
+            assert!(previous.end != region.start || previous.region_type != region.region_type);
+        }
+        previous = Some(region);
+    }
+
+    let totals = inspect::memory_totals(map);
+    let sum: u64 = map.iter().map(|r| r.range.end_addr() - r.range.start_addr()).sum();
+    assert_eq!(totals.total(), sum);
+    assert!(totals.usable > 0 && totals.in_use > 0);
+
+    let usable: u64 = map.iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| r.range.end_addr() - r.range.start_addr())
+        .sum();
+    assert_eq!(totals.usable, usable);
+}
+
+#[test_case]
+fn reports_are_printed() {
+    inspect::print_memory_map(memory_map());
+    memory::with_kernel_memory(|mem| inspect::print_page_tables(&mem.mapper));
+}
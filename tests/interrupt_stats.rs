@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(asm)]
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use rost::interrupts::{self, exceptions, register_irq, unregister_irq};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rost::init();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+#[test_case]
+fn timer_interrupts_are_counted() {
+    let before = interrupts::stats().irq(0);
+    for _ in 0..3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(interrupts::stats().irq(0) >= before + 3);
+}
+
+#[test_case]
+fn exceptions_are_counted() {
+    let before = interrupts::stats();
+    x86_64::instructions::interrupts::int3();
+    exceptions::recover_next(2);
+    unsafe { asm!("ud2") };
+
+    let after = interrupts::stats();
+    assert_eq!(after.counts[3], before.counts[3] + 1);
+    assert_eq!(after.counts[6], before.counts[6] + 1);
+    assert_eq!(after.exceptions(), before.exceptions() + 2);
+}
+
+#[test_case]
+fn registered_irqs_are_counted() {
+    fn handler(_line: u8) {}
+    register_irq(5, handler).unwrap();
+    let before = interrupts::stats().irq(5);
+    unsafe { asm!("int 37", "int 37") };
+    assert_eq!(interrupts::stats().irq(5), before + 2);
+    unregister_irq(5, handler).unwrap();
+}
+
+#[test_case]
+fn lapic_spurious_interrupts_are_counted() {
+    let before = interrupts::stats();
+    unsafe { asm!("int 0xff") };
+
+    let after = interrupts::stats();
+    assert_eq!(after.spurious, before.spurious + 1);
+    assert_eq!(after.counts[0xff], before.counts[0xff]);
+}
+
+/// Formats without the heap, which isn't set up
+struct Buffer {
+    bytes: [u8; 4096],
+    len: usize,
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn stats_are_formatted_like_proc_interrupts() {
+    let stats = interrupts::stats();
+    let mut buffer = Buffer { bytes: [0; 4096], len: 0 };
+    write!(buffer, "{}", stats).unwrap();
+    let table = core::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap();
+
+    assert_eq!(table.lines().next().map(str::trim), Some("CPU0"));
+
+    // The timer has a handler and fired, the PICs deliver it
+    let timer = table.lines().find(|line| line.starts_with(" 32:")).expect("no IRQ0 row");
+    let mut words = timer.split_whitespace().skip(1);
+    assert_eq!(words.next().and_then(|count| count.parse().ok()), Some(stats.irq(0)));
+    assert_eq!(words.next(), Some("XT-PIC"));
+    assert_eq!(words.next(), Some("IRQ0"));
+
+    let spurious = table.lines().last().expect("empty table");
+    let mut words = spurious.split_whitespace();
+    assert_eq!(words.next(), Some("SPU:"));
+    assert_eq!(words.next().and_then(|count| count.parse().ok()), Some(stats.spurious));
+}
+
+#[test_case]
+fn stats_can_be_printed() {
+    interrupts::print_stats();
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(asm)]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rost::interrupts::{self, irq, register_irq, unregister_irq, IrqError};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rost::init();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Nothing is wired to IRQ 5 on QEMU, the tests raise it with `int 37`
+const LINE: u8 = 5;
+
+static FIRST: AtomicUsize = AtomicUsize::new(0);
+static SECOND: AtomicUsize = AtomicUsize::new(0);
+
+fn first(line: u8) {
+    assert_eq!(line, LINE);
+    FIRST.fetch_add(1, Ordering::SeqCst);
+}
+
+fn second(_line: u8) {
+    SECOND.fetch_add(1, Ordering::SeqCst);
+}
+
+fn raise_irq5() {
+    unsafe { asm!("int 37") };
+}
+
+#[test_case]
+fn handlers_are_called() {
+    register_irq(LINE, first).unwrap();
+    let before = FIRST.load(Ordering::SeqCst);
+    raise_irq5();
+    assert_eq!(FIRST.load(Ordering::SeqCst), before + 1);
+
+    unregister_irq(LINE, first).unwrap();
+    raise_irq5();
+    assert_eq!(FIRST.load(Ordering::SeqCst), before + 1);
+}
+
+#[test_case]
+fn lines_can_be_shared() {
+    register_irq(LINE, first).unwrap();
+    register_irq(LINE, second).unwrap();
+    let before = (FIRST.load(Ordering::SeqCst), SECOND.load(Ordering::SeqCst));
+    raise_irq5();
+    assert_eq!(FIRST.load(Ordering::SeqCst), before.0 + 1);
+    assert_eq!(SECOND.load(Ordering::SeqCst), before.1 + 1);
+
+    unregister_irq(LINE, first).unwrap();
+    unregister_irq(LINE, second).unwrap();
+}
+
+#[test_case]
+fn lines_are_masked_without_handlers() {
+    assert!(irq::is_masked_on_pic(LINE));
+    register_irq(LINE, first).unwrap();
+    assert!(!irq::is_masked_on_pic(LINE));
+    unregister_irq(LINE, first).unwrap();
+    assert!(irq::is_masked_on_pic(LINE));
+
+    // The timer, the keyboard and the cascade
+    assert!(!irq::is_masked_on_pic(0));
+    assert!(!irq::is_masked_on_pic(1));
+    assert!(!irq::is_masked_on_pic(irq::CASCADE_LINE));
+}
+
+#[test_case]
+fn registration_errors() {
+    assert_eq!(register_irq(16, first), Err(IrqError::InvalidLine));
+    assert_eq!(register_irq(irq::CASCADE_LINE, first), Err(IrqError::InvalidLine));
+    assert_eq!(unregister_irq(LINE, first), Err(IrqError::NotRegistered));
+
+    register_irq(LINE, first).unwrap();
+    assert_eq!(register_irq(LINE, first), Err(IrqError::AlreadyRegistered));
+    unregister_irq(LINE, first).unwrap();
+}
+
+#[test_case]
+fn too_many_handlers() {
+    // Different bodies, identical functions could be merged
+    fn a(_: u8) { SECOND.fetch_add(10, Ordering::SeqCst); }
+    fn b(_: u8) { SECOND.fetch_add(20, Ordering::SeqCst); }
+    fn c(_: u8) { SECOND.fetch_add(30, Ordering::SeqCst); }
+    fn d(_: u8) { SECOND.fetch_add(40, Ordering::SeqCst); }
+    let handlers: [fn(u8); 4] = [a, b, c, d];
+    assert_eq!(handlers.len(), irq::MAX_SHARED_HANDLERS);
+
+    for &handler in &handlers {
+        register_irq(LINE, handler).unwrap();
+    }
+    assert_eq!(register_irq(LINE, first), Err(IrqError::TooManyHandlers));
+    for &handler in &handlers {
+        unregister_irq(LINE, handler).unwrap();
+    }
+}
+
+#[test_case]
+fn spurious_irq7_is_detected() {
+    register_irq(7, first).unwrap();
+    let (before, spurious) = (FIRST.load(Ordering::SeqCst), interrupts::stats().spurious);
+
+    // Not in service on the PIC, like a spurious one
+    unsafe { asm!("int 39") };
+    assert_eq!(interrupts::stats().spurious, spurious + 1);
+    assert_eq!(FIRST.load(Ordering::SeqCst), before);
+
+    unregister_irq(7, first).unwrap();
+}
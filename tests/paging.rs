@@ -0,0 +1,178 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Page, PageTableFlags as Flags, PhysFrame, Size2MiB, Size4KiB};
+use rost::memory::{self, BuddyFrameAllocator, KernelMemory, MapError};
+use rost::memory::paging::UnmapError;
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Unused region of the address space, every test gets its own 1GiB
+fn test_page(test: u64, n: u64) -> Page {
+    Page::containing_address(VirtAddr::new(0x_6000_0000_0000 + (test << 30) + n * 4096))
+}
+
+fn rw() -> Flags {
+    Flags::PRESENT | Flags::WRITABLE
+}
+
+#[test_case]
+fn map_and_translate() {
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let page = test_page(0, 0);
+        let frame: PhysFrame = frame_allocator.allocate(0).unwrap();
+        unsafe { mapper.map(page, frame, rw(), frame_allocator).unwrap().flush() };
+
+        let translation = mapper.translate(page.start_address() + 42u64).unwrap();
+        assert_eq!(translation.addr, frame.start_address() + 42u64);
+        assert_eq!(translation.page_size, 4096);
+        assert!(translation.flags.contains(rw()));
+
+        // Written through the new page, read through the physical memory mapping
+        unsafe { *page.start_address().as_mut_ptr::<u64>() = 0x1234_5678 };
+        let alias = mapper.physical_memory_offset() + frame.start_address().as_u64();
+        assert_eq!(unsafe { *alias.as_ptr::<u64>() }, 0x1234_5678);
+
+        let err = unsafe { mapper.map(page, frame, rw(), frame_allocator) }.err();
+        assert_eq!(err, Some(MapError::PageAlreadyMapped(frame.start_address())));
+    });
+}
+
+#[test_case]
+fn update_flags() {
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let page = test_page(1, 0);
+        let frame: PhysFrame = frame_allocator.allocate(0).unwrap();
+        unsafe { mapper.map(page, frame, rw(), frame_allocator).unwrap().flush() };
+
+        mapper.update_flags(page, Flags::PRESENT | Flags::NO_EXECUTE).unwrap().flush();
+        let flags = mapper.translate(page.start_address()).unwrap().flags;
+        assert!(!flags.contains(Flags::WRITABLE));
+        assert!(flags.contains(Flags::NO_EXECUTE));
+
+        let unmapped = test_page(1, 1);
+        assert_eq!(mapper.update_flags(unmapped, rw()).err(), Some(UnmapError::PageNotMapped));
+    });
+}
+
+#[test_case]
+fn unmap_reclaims_tables() {
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let page = test_page(2, 0);
+        let map_unmap = |mapper: &mut memory::PageMapper, fa: &mut BuddyFrameAllocator| {
+            let frame = fa.allocate(0).unwrap();
+            unsafe { mapper.map(page, frame, rw(), fa).unwrap().flush() };
+            let (unmapped, flush) = mapper.unmap(page, fa).unwrap();
+            flush.flush();
+            assert_eq!(unmapped, frame);
+            unsafe { fa.free(unmapped, 0) };
+        };
+
+        // The first time may create a L3 table, which is kept
+        map_unmap(mapper, frame_allocator);
+        let free = frame_allocator.free_frames();
+        map_unmap(mapper, frame_allocator);
+        assert_eq!(frame_allocator.free_frames(), free);
+
+        assert_eq!(mapper.translate(page.start_address()), None);
+        assert_eq!(mapper.unmap(page, frame_allocator).err(), Some(UnmapError::PageNotMapped));
+    });
+}
+
+#[test_case]
+fn ranges() {
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        // More pages than a FlushBatch holds
+        let pages = Page::range(test_page(3, 0), test_page(3, 100));
+        let free = frame_allocator.free_frames();
+
+        let batch = unsafe { mapper.map_range(pages, rw(), frame_allocator) }.unwrap();
+        assert_eq!(batch.len(), 100);
+        batch.flush();
+        for page in pages {
+            unsafe { *page.start_address().as_mut_ptr::<u64>() = page.start_address().as_u64() };
+        }
+
+        mapper.update_flags_range(pages, Flags::PRESENT).unwrap().flush();
+        let mut read_only = pages;
+        assert!(read_only.all(|p| !mapper.translate(p.start_address()).unwrap().flags.contains(Flags::WRITABLE)));
+
+        unsafe { mapper.unmap_range(pages, frame_allocator) }.unwrap().flush();
+        let mut unmapped = pages;
+        assert!(unmapped.all(|p| mapper.translate(p.start_address()).is_none()));
+        // Only the L3 table is left
+        assert!(free - frame_allocator.free_frames() <= 1);
+    });
+}
+
+#[test_case]
+fn map_range_rolls_back_on_error() {
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let taken = test_page(5, 10);
+        let frame: PhysFrame = frame_allocator.allocate(0).unwrap();
+        unsafe { mapper.map(taken, frame, rw(), frame_allocator).unwrap().flush() };
+        let free = frame_allocator.free_frames();
+
+        let pages = Page::range(test_page(5, 0), test_page(5, 20));
+        let err = unsafe { mapper.map_range(pages, rw(), frame_allocator) }.err();
+        assert_eq!(err, Some(MapError::PageAlreadyMapped(frame.start_address())));
+
+        // The pages before are unmapped again and their frames freed
+        let mut before = Page::range(test_page(5, 0), taken);
+        assert!(before.all(|p| mapper.translate(p.start_address()).is_none()));
+        assert_eq!(frame_allocator.free_frames(), free);
+        assert!(mapper.translate(taken.start_address()).is_some());
+    });
+}
+
+#[test_case]
+fn huge_page() {
+    use rost::memory::buddy::MAX_ORDER;
+
+    memory::with_kernel_memory(|KernelMemory { mapper, frame_allocator }| {
+        let page: Page<Size2MiB> = Page::containing_address(test_page(4, 0).start_address());
+        let frame = frame_allocator.allocate(MAX_ORDER).unwrap();
+        let frame: PhysFrame<Size2MiB> = PhysFrame::containing_address(frame.start_address());
+        unsafe { mapper.map(page, frame, rw(), frame_allocator).unwrap().flush() };
+
+        let addr = page.start_address() + 0x1_2345u64;
+        let translation = mapper.translate(addr).unwrap();
+        assert_eq!(translation.page_size, 2 * 1024 * 1024);
+        assert_eq!(translation.addr, frame.start_address() + 0x1_2345u64);
+
+        // The 4KiB pages inside are part of the huge page
+        let small: Page<Size4KiB> = Page::containing_address(addr);
+        assert_eq!(mapper.unmap(small, frame_allocator).err(), Some(UnmapError::ParentEntryHugePage));
+
+        let (unmapped, flush) = mapper.unmap(page, frame_allocator).unwrap();
+        flush.flush();
+        assert_eq!(unmapped, frame);
+        assert_eq!(mapper.translate(addr), None);
+    });
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use rost::arch::pit::{AccessMode, Channel, OperatingMode, MAXIMAL_DELAY, PIT_FREQUENCY};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rost::init();
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Channel 1 counts on QEMU without raising anything, channel 0 is the timer
+fn channel1() -> Channel {
+    unsafe { Channel::new(1) }
+}
+
+#[test_case]
+fn reload_values_are_rounded() {
+    let mut channel = channel1();
+    channel.set_frequency(OperatingMode::RateGenerator, 1000);
+    assert_eq!(channel.reload_value(), Some(1193));
+    channel.set_delay(OperatingMode::RateGenerator, 1000);
+    assert_eq!(channel.reload_value(), Some(1193));
+    channel.set_delay(OperatingMode::RateGenerator, 10);
+    assert_eq!(channel.reload_value(), Some(12));
+    // 65536 is written as 0
+    channel.set_delay(OperatingMode::RateGenerator, MAXIMAL_DELAY);
+    assert_eq!(channel.reload_value(), Some(0));
+    channel.set_frequency(OperatingMode::SquareWaveGenerator, 1000);
+    assert_eq!(channel.reload_value(), Some(1192));
+    // A reload of 1 is rounded up, not down to 0 which means 65536
+    channel.set_frequency(OperatingMode::SquareWaveGenerator, 1_000_000);
+    assert_eq!(channel.reload_value(), Some(2));
+    channel.set_frequency(OperatingMode::RateGenerator, PIT_FREQUENCY);
+    assert_eq!(channel.reload_value(), Some(2));
+}
+
+#[test_case]
+fn mode_is_written() {
+    let mut channel = channel1();
+    channel.set_frequency(OperatingMode::RateGenerator, 1000);
+    let status = channel.read_status();
+    assert_eq!(status.operating_mode(), OperatingMode::RateGenerator);
+    assert_eq!(status.access_mode(), AccessMode::LobyteHibyte);
+    assert!(!status.bcd());
+
+    channel.set_frequency(OperatingMode::SquareWaveGenerator, 1000);
+    assert_eq!(channel.read_status().operating_mode(), OperatingMode::SquareWaveGenerator);
+}
+
+#[test_case]
+fn count_goes_down() {
+    let mut channel = channel1();
+    channel.set_frequency(OperatingMode::RateGenerator, 100);
+    let reload = channel.reload_value().unwrap();
+
+    let first = channel.read_count();
+    let mut second = channel.read_count();
+    for _ in 0..1000 {
+        if second != first {
+            break;
+        }
+        second = channel.read_count();
+    }
+    assert!(first <= reload && second <= reload);
+    assert_ne!(first, second);
+}
+
+#[test_case]
+fn one_shot_fires_once() {
+    let mut channel = channel1();
+    channel.set_delay(OperatingMode::InterruptOnTerminalCount, 200);
+    assert!(!channel.read_status().output());
+
+    let mut fired = false;
+    for _ in 0..1_000_000 {
+        if channel.read_status().output() {
+            fired = true;
+            break;
+        }
+    }
+    assert!(fired, "the delay never elapsed");
+
+    // The output stays up until the channel is armed again
+    assert!(channel.read_status().output());
+    channel.set_delay(OperatingMode::InterruptOnTerminalCount, 200);
+    assert!(!channel.read_status().output());
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+
+
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use rost::allocator::{heap_size, HEAP_SIZE};
+use rost::allocator::slab::BLOCK_SIZES;
+
+#[test_case]
+fn every_size_class() {
+    for &size in BLOCK_SIZES.iter() {
+        let mut v: Vec<u8> = Vec::with_capacity(size);
+        v.resize(size, 0xAB);
+        assert_eq!(v.as_ptr() as usize % size.min(4096), 0);
+        assert!(v.iter().all(|&b| b == 0xAB));
+    }
+}
+
+#[test_case]
+fn large_allocations() {
+    let v: Vec<u64> = (0..10_000).collect();
+    assert_eq!(v.iter().sum::<u64>(), 9_999 * 10_000 / 2);
+}
+
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+/// Freed blocks are reused, so churning through many allocations
+/// of mixed sizes must not make the heap grow
+#[test_case]
+fn heap_stable_under_churn() {
+    let mut live: Vec<Vec<u8>> = Vec::with_capacity(64);
+    for i in 0..64 {
+        live.push(Vec::with_capacity(BLOCK_SIZES[i % BLOCK_SIZES.len()]));
+    }
+    let churn = |live: &mut Vec<Vec<u8>>| {
+        for i in 0..100_000 {
+            let slot = (i * 7) % live.len();
+            live[slot] = Vec::with_capacity(BLOCK_SIZES[i % BLOCK_SIZES.len()]);
+        }
+    };
+
+    // The first round fills the caches
+    churn(&mut live);
+    let size = heap_size();
+    churn(&mut live);
+    assert_eq!(heap_size(), size);
+}
@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::BootInfo;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use rost::task::{Task, executor::Executor};
+use rost::time::{self, sleep, sleep_until, timeout, Duration, Elapsed, Instant};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    time::init(1000);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn run(task: impl core::future::Future<Output = ()> + 'static) {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(task));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn sleep_lasts_about_as_long_as_asked() {
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    run(async {
+        let start = Instant::now();
+        sleep(Duration::from_millis(100)).await;
+        ELAPSED.store(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+    });
+
+    // A period of rounding up, a period late at most
+    let elapsed = Duration::from_nanos(ELAPSED.load(Ordering::SeqCst));
+    let slack = time::tick_period() * 3;
+    assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(100) + slack, "{:?}", elapsed);
+}
+
+#[test_case]
+fn sleepers_wake_up_in_deadline_order() {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    static RANKS: [AtomicUsize; 3] = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+
+    async fn sleeper(index: usize, millis: u64) {
+        sleep(Duration::from_millis(millis)).await;
+        RANKS[index].store(NEXT.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(sleeper(0, 30)));
+    executor.spawn(Task::new(sleeper(1, 10)));
+    executor.spawn(Task::new(sleeper(2, 20)));
+    executor.run_until_complete();
+
+    let ranks: [usize; 3] = [
+        RANKS[0].load(Ordering::SeqCst),
+        RANKS[1].load(Ordering::SeqCst),
+        RANKS[2].load(Ordering::SeqCst),
+    ];
+    assert_eq!(ranks, [2, 0, 1]);
+}
+
+#[test_case]
+fn sleep_until_a_past_instant_is_immediate() {
+    static TICKS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    run(async {
+        let ticks = time::ticks();
+        sleep_until(Instant::BOOT).await;
+        TICKS.store(time::ticks() - ticks, Ordering::SeqCst);
+    });
+    assert!(TICKS.load(Ordering::SeqCst) <= 1);
+}
+
+#[test_case]
+fn timeout_elapses() {
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    run(async {
+        let start = Instant::now();
+        let result = timeout(Duration::from_millis(20), sleep(Duration::from_secs(10))).await;
+        assert_eq!(result, Err(Elapsed));
+        ELAPSED.store(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+    });
+
+    let elapsed = Duration::from_nanos(ELAPSED.load(Ordering::SeqCst));
+    assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(30), "{:?}", elapsed);
+}
+
+#[test_case]
+fn timeouts_dont_leave_timers_behind() {
+    use rost::time::timer::pending_timers;
+
+    let before = pending_timers();
+    run(async {
+        let result = timeout(Duration::from_millis(10), sleep(Duration::from_secs(10))).await;
+        assert_eq!(result, Err(Elapsed));
+        let result = timeout(Duration::from_secs(10), async { 42 }).await;
+        assert_eq!(result, Ok(42));
+    });
+    assert_eq!(pending_timers(), before);
+}
+
+#[test_case]
+fn endless_sleeps_saturate() {
+    let forever = Duration::from_secs(u64::MAX);
+    assert_eq!(sleep(forever).deadline(), Instant::from_nanos(u64::MAX));
+
+    run(async move {
+        let result = timeout(forever, async { 42 }).await;
+        assert_eq!(result, Ok(42));
+    });
+}
+
+#[test_case]
+fn timeout_lets_quick_futures_complete() {
+    run(async {
+        let result = timeout(Duration::from_millis(100), async {
+            sleep(Duration::from_millis(5)).await;
+            42
+        }).await;
+        assert_eq!(result, Ok(42));
+    });
+}
@@ -0,0 +1,139 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::BootInfo;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use rost::arch::pit::{Channel, OperatingMode};
+use rost::arch::port::Port;
+use rost::arch::speaker::{self, pitch, Note, SpeakerError};
+use rost::task::{Task, executor::Executor};
+use rost::time::{self, timeout, Duration, Elapsed, Instant};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    use rost::allocator;
+    use rost::memory::{self, BuddyFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap alloc failed");
+    time::init(1000);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn speaker_port() -> u8 {
+    unsafe { Port::<u8>::new(0x61).read() }
+}
+
+fn run(task: impl core::future::Future<Output = ()> + 'static) {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(task));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn play_gates_channel_2() {
+    speaker::play(pitch::A4).unwrap();
+    assert_eq!(speaker_port() & 0b11, 0b11);
+    assert!(speaker::is_playing());
+    assert_eq!(speaker::frequency(), Some(pitch::A4));
+
+    // Only reads, the driver keeps the channel
+    let channel = unsafe { Channel::new(2) };
+    assert_eq!(channel.read_status().operating_mode(), OperatingMode::SquareWaveGenerator);
+
+    speaker::stop();
+    assert_eq!(speaker_port() & 0b11, 0);
+    assert!(!speaker::is_playing());
+}
+
+#[test_case]
+fn stop_keeps_the_other_bits() {
+    let before = speaker_port() & !0b11;
+    speaker::play(pitch::C4).unwrap();
+    speaker::stop();
+    assert_eq!(speaker_port() & !0b11, before);
+}
+
+#[test_case]
+fn frequencies_out_of_range_are_rejected() {
+    assert_eq!(speaker::play(15), Err(SpeakerError::FrequencyOutOfRange(15)));
+    assert_eq!(speaker::play(2_000_000), Err(SpeakerError::FrequencyOutOfRange(2_000_000)));
+    assert!(!speaker::is_playing());
+
+    // 0 is a rest
+    speaker::play(pitch::A4).unwrap();
+    assert_eq!(speaker::play(0), Ok(()));
+    assert!(!speaker::is_playing());
+
+    run(async {
+        let result = speaker::beep(10, Duration::from_millis(10)).await;
+        assert_eq!(result, Err(SpeakerError::FrequencyOutOfRange(10)));
+    });
+}
+
+#[test_case]
+fn beep_lasts_its_duration() {
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    run(async {
+        let start = Instant::now();
+        speaker::beep(pitch::E4, Duration::from_millis(30)).await.unwrap();
+        ELAPSED.store(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+    });
+
+    assert!(!speaker::is_playing());
+    assert!(Duration::from_nanos(ELAPSED.load(Ordering::SeqCst)) >= Duration::from_millis(30));
+}
+
+#[test_case]
+fn dropped_beep_stops() {
+    run(async {
+        let result = timeout(Duration::from_millis(10), speaker::beep(pitch::G4, Duration::from_secs(10))).await;
+        assert_eq!(result, Err(Elapsed));
+        assert!(!speaker::is_playing());
+    });
+}
+
+#[test_case]
+fn notes_are_played_in_sequence() {
+    static TUNE: [Note; 4] = [
+        Note::new(pitch::C4, 20),
+        Note::new(pitch::E4, 20),
+        Note::rest(20),
+        Note::new(pitch::G4, 20),
+    ];
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    run(async {
+        let start = Instant::now();
+        speaker::play_notes(&TUNE).await.unwrap();
+        ELAPSED.store(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+    });
+
+    assert!(!speaker::is_playing());
+    assert_eq!(speaker::frequency(), Some(pitch::G4));
+    assert!(Duration::from_nanos(ELAPSED.load(Ordering::SeqCst)) >= Duration::from_millis(80));
+}
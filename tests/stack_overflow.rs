@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(asm)]
 
 use core::panic::PanicInfo;
 
@@ -11,18 +12,39 @@ fn panic(info: &PanicInfo) -> ! {
 }
 
 use rost::{serial_print};
+use rost::memory::{self, BuddyFrameAllocator, KernelStack};
+use bootloader::BootInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+/// Guard page of the stack overflowed by the test
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
 
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
 
     rost::gdt::init();
     init_test_idt();
 
-    // trigger a stack overflow
-    stack_overflow();
-
-    panic!("Execution continued after stack overflow");
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    // The double fault handler now runs on a kernel stack too
+    rost::gdt::install_ist_stacks();
+
+    let stack = KernelStack::new(4096 * 4).expect("failed to allocate a stack");
+    GUARD_PAGE.store(stack.guard_page().start_address().as_u64(), Ordering::Relaxed);
+
+    // trigger a stack overflow, on the new stack
+    unsafe {
+        asm!("mov rsp, {}", "call {}",
+            in(reg) stack.top().as_u64(), in(reg) stack_overflow as usize,
+            options(noreturn));
+    }
 }
 
 #[allow(unconditional_recursion)]
@@ -39,9 +61,19 @@ extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: &mut InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    serial_println!("[ok]");
-//    print_isf(_stack_frame);
-    exit_qemu(QemuExitCode::Success);
+    use x86_64::registers::control::Cr2;
+
+    let address = Cr2::read();
+    let guard_page = GUARD_PAGE.load(Ordering::Relaxed);
+    if rost::memory::stack::is_guard_page(address) && address.align_down(4096u64).as_u64() == guard_page {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: double fault at {:?}, not in the guard page {:#x}", address, guard_page);
+        print_isf(_stack_frame);
+        exit_qemu(QemuExitCode::Failed);
+    }
     loop {}
 }
 
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use rost::time::{self, Duration, Instant};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    rost::init();
+    time::init(1000);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+/// Halts until the next timer interrupt, returns the tick count then
+fn next_tick() -> u64 {
+    let ticks = time::ticks();
+    for _ in 0..100 {
+        x86_64::instructions::hlt();
+        if time::ticks() != ticks {
+            return time::ticks();
+        }
+    }
+    panic!("the timer doesn't tick");
+}
+
+#[test_case]
+fn ticks_advance() {
+    let before = time::ticks();
+    assert!(next_tick() > before);
+}
+
+#[test_case]
+fn tick_period_comes_from_the_reload_value() {
+    // 1193182 / 1000 rounds to a reload of 1193, 999847 ns
+    let period = time::tick_period();
+    assert!(period > Duration::from_micros(999), "{:?}", period);
+    assert!(period < Duration::from_micros(1001), "{:?}", period);
+}
+
+#[test_case]
+fn clock_advances_by_a_period_per_tick() {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    let (start, ticks) = without_interrupts(|| (Instant::now(), time::ticks()));
+    next_tick();
+    let (end, end_ticks) = without_interrupts(|| (Instant::now(), time::ticks()));
+
+    // Each conversion to nanoseconds rounds down
+    let ticks = end_ticks - ticks;
+    let elapsed = end - start;
+    let expected = time::tick_period() * ticks as u32;
+    let error = if elapsed > expected { elapsed - expected } else { expected - elapsed };
+    assert!(error <= Duration::from_nanos(ticks), "{:?} for {:?}", elapsed, expected);
+}
+
+#[test_case]
+fn uptime_is_monotonic() {
+    let mut last = time::uptime();
+    for _ in 0..5 {
+        next_tick();
+        let uptime = time::uptime();
+        assert!(uptime > last);
+        last = uptime;
+    }
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let a = Instant::from_nanos(1_000);
+    let b = a + Duration::from_nanos(500);
+    assert_eq!(b.as_nanos(), 1_500);
+    assert_eq!(b - a, Duration::from_nanos(500));
+    assert_eq!(a - b, Duration::from_nanos(0));
+    assert_eq!(b - Duration::from_nanos(1_500), Instant::BOOT);
+    assert_eq!(a.checked_sub(Duration::from_micros(2)), None);
+    assert_eq!(Instant::BOOT.checked_add(Duration::from_secs(u64::MAX)), None);
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rost::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::PageTableFlags as Flags;
+use rost::memory::{self, BuddyFrameAllocator, ioremap, vmalloc};
+use rost::memory::vmalloc::{VMALLOC_END, VMALLOC_START};
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    rost::hlt_loop();
+}
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+
+fn translate(addr: VirtAddr) -> Option<memory::paging::Translation> {
+    memory::with_kernel_memory(|mem| mem.mapper.translate(addr))
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames())
+}
+
+#[test_case]
+fn vmalloc_maps_contiguous_range() {
+    let mut area = vmalloc(5 * 4096 + 1).unwrap();
+    assert_eq!(area.size(), 6 * 4096);
+    let start = area.start().as_u64();
+    assert!(start >= VMALLOC_START && start + 6 * 4096 <= VMALLOC_END);
+
+    let words = area.size() / 8;
+    let ptr = area.as_mut_ptr::<u64>();
+    for i in 0..words {
+        unsafe { ptr.add(i).write_volatile(i as u64) };
+    }
+    for i in 0..words {
+        assert_eq!(unsafe { ptr.add(i).read_volatile() }, i as u64);
+    }
+
+    let flags = translate(area.start()).unwrap().flags;
+    assert!(flags.contains(Flags::WRITABLE | Flags::NO_EXECUTE));
+}
+
+#[test_case]
+fn areas_are_separated_by_guard_pages() {
+    let a = vmalloc(4096).unwrap();
+    let b = vmalloc(4096).unwrap();
+    let guard = a.start() + 4096u64;
+    assert!(translate(guard).is_none());
+    assert_ne!(b.start(), guard);
+}
+
+#[test_case]
+fn drop_unmaps_and_frees() {
+    // The first area of the region allocated its L3 table, which is never freed
+    drop(vmalloc(4096).unwrap());
+
+    let free = free_frames();
+    let area = vmalloc(64 * 4096).unwrap();
+    let start = area.start();
+    assert!(free_frames() < free);
+
+    drop(area);
+    assert!(translate(start).is_none());
+    assert_eq!(free_frames(), free);
+
+    // The range is reused
+    let again = vmalloc(64 * 4096).unwrap();
+    assert_eq!(again.start(), start);
+}
+
+#[test_case]
+fn ioremap_vga_buffer() {
+    let free = free_frames();
+    // Unaligned on purpose: the second line of the text buffer
+    let phys = PhysAddr::new(0xb8000 + 160);
+    let mut area = unsafe { ioremap(phys, 160) }.unwrap();
+    assert_eq!(area.start().as_u64() % 4096, 160);
+
+    let translation = translate(area.start()).unwrap();
+    assert_eq!(translation.addr, phys);
+    assert!(translation.flags.contains(Flags::NO_CACHE | Flags::WRITE_THROUGH));
+
+    let cell = area.as_mut_ptr::<u16>();
+    let through_physical_mapping = (memory::physical_memory_offset() + phys.as_u64()).as_ptr::<u16>();
+    unsafe {
+        cell.write_volatile(0x0f41);
+        assert_eq!(through_physical_mapping.read_volatile(), 0x0f41);
+    }
+
+    // Only the page tables are freed, not the VGA frame
+    drop(area);
+    assert_eq!(free_frames(), free);
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+
+use core::panic::PanicInfo;
+
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rost::test_panic_handler(info)
+}
+
+use rost::{serial_print};
+use rost::memory::{self, BuddyFrameAllocator};
+use rost::memory::protect::{Area, Diagnosis, SegmentKind, Violation};
+use bootloader::BootInfo;
+use x86_64::VirtAddr;
+
+#[no_mangle]
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    serial_print!("write_text::write_text...\t");
+
+    rost::gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::install(mapper, frame_allocator);
+    memory::protect::protect_kernel();
+
+    // Overwrite the first bytes of a function
+    let text = _start as *const () as *mut u8;
+    unsafe { text.write_volatile(0xcc) };
+
+    panic!("Execution continued after writing to the kernel text");
+}
+
+
+use rost::{exit_qemu, QemuExitCode, serial_println};
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    let diagnosis = memory::protect::diagnose(Cr2::read(), error_code);
+    if diagnosis == Some(Diagnosis { violation: Violation::Write, area: Area::Kernel(SegmentKind::Text) }) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: page fault at {:?} diagnosed as {:?} ({:?})",
+            Cr2::read(), diagnosis, error_code);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+
+
+use lazy_static::lazy_static;
+use x86_64::structures::idt::InterruptDescriptorTable;
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}